@@ -0,0 +1,384 @@
+use std::fmt::Display;
+
+use itertools::Itertools;
+
+use crate::token::SourceLocation;
+
+#[derive(Clone)]
+pub struct Name {
+    pub name: String,
+    pub location: SourceLocation,
+}
+
+#[derive(Clone, Copy)]
+pub enum BinaryOp {
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Rem,
+    Pow,
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+    And,
+    Or,
+}
+
+#[derive(Clone, Copy)]
+pub enum UnaryOp {
+    Neg,
+    Not,
+}
+
+pub struct Expr {
+    pub kind: ExprKind,
+    pub location: SourceLocation,
+}
+
+pub enum ExprKind {
+    Integer(i64),
+    Float(f64),
+    Bool(bool),
+    String(String),
+    // A string literal with interpolations, whose parts are concatenated
+    Template(Vec<Expr>),
+    Name(String),
+    Lambda {
+        params: Vec<Name>,
+        body: Box<Expr>,
+    },
+    Call {
+        callee: Box<Expr>,
+        args: Vec<Expr>,
+    },
+    Block(Vec<Stmt>),
+    Binary {
+        op: BinaryOp,
+        lhs: Box<Expr>,
+        rhs: Box<Expr>,
+    },
+    Unary {
+        op: UnaryOp,
+        operand: Box<Expr>,
+    },
+    List(Vec<Expr>),
+    // Key and value pairs in the order they were written
+    Map(Vec<(Expr, Expr)>),
+    Index {
+        target: Box<Expr>,
+        index: Box<Expr>,
+    },
+    // `record { x, y }`, which evaluates to a new record type
+    RecordType(Vec<Name>),
+    // A call with named arguments, which constructs a record
+    Construct {
+        callee: Box<Expr>,
+        fields: Vec<(Name, Expr)>,
+    },
+    Field {
+        target: Box<Expr>,
+        field: Name,
+    },
+    // `target with { x: 1 }`, a copy of a record with some fields replaced
+    Update {
+        target: Box<Expr>,
+        fields: Vec<(Name, Expr)>,
+    },
+    Match {
+        scrutinee: Box<Expr>,
+        arms: Vec<Arm>,
+    },
+    If {
+        cond: Box<Expr>,
+        then: Box<Expr>,
+        // Either a block or, for `else if`, another `If`
+        otherwise: Option<Box<Expr>>,
+    },
+    // `while cond { ... }`, which evaluates to nil like other loops
+    While {
+        label: Option<Name>,
+        cond: Box<Expr>,
+        body: Box<Expr>,
+    },
+    // `for x in items { ... }` or `for k, v in items { ... }`. With an `end`,
+    // as in `for i in 0..n { ... }`, it counts from `iterable` up to `end`.
+    For {
+        label: Option<Name>,
+        names: Vec<Name>,
+        iterable: Box<Expr>,
+        end: Option<Box<Expr>>,
+        body: Box<Expr>,
+    },
+    // `break` or `continue`, with the label of the loop if it isn't the
+    // innermost one
+    Break(Option<Name>),
+    Continue(Option<Name>),
+}
+
+pub struct Arm {
+    pub pattern: Pattern,
+    pub guard: Option<Expr>,
+    pub body: Expr,
+}
+
+pub struct Pattern {
+    pub kind: PatternKind,
+    pub location: SourceLocation,
+}
+
+pub enum PatternKind {
+    // `_`, which matches anything
+    Wildcard,
+    Binding(String),
+    Integer(i64),
+    Bool(bool),
+    String(String),
+    // A union variant with patterns for its fields in order, such as
+    // `Some(x)`, or `None` for a variant without fields
+    Variant {
+        name: Name,
+        fields: Vec<Pattern>,
+    },
+    // `[a, b]`, or `[a, b, ..rest]` to match lists at least that long, where
+    // `rest` is a wildcard or binding for the remaining items
+    List {
+        items: Vec<Pattern>,
+        rest: Option<Box<Pattern>>,
+    },
+}
+
+// A variant of a union declaration, such as `Some(x)`
+pub struct Variant {
+    pub name: Name,
+    pub fields: Vec<Name>,
+}
+
+pub enum Stmt {
+    // `let x = value`, which always introduces a new binding, shadowing any
+    // other binding of the name
+    Let { name: Name, value: Expr },
+    // `x = value`, which assigns to `x` if it's in scope and otherwise binds it
+    Binding { name: Name, value: Expr },
+    // `Option = Some(x) | None`, which binds the union and each variant
+    Union { name: Name, variants: Vec<Variant> },
+    // Assigns to an element of a list, as in `xs[i] = x`
+    Assign { target: Expr, value: Expr },
+    Expr(Expr),
+}
+
+pub struct Program {
+    pub stmts: Vec<Stmt>,
+}
+
+impl Display for BinaryOp {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let op = match self {
+            BinaryOp::Add => "+",
+            BinaryOp::Sub => "-",
+            BinaryOp::Mul => "*",
+            BinaryOp::Div => "/",
+            BinaryOp::Rem => "%",
+            BinaryOp::Pow => "**",
+            BinaryOp::Eq => "==",
+            BinaryOp::Ne => "!=",
+            BinaryOp::Lt => "<",
+            BinaryOp::Le => "<=",
+            BinaryOp::Gt => ">",
+            BinaryOp::Ge => ">=",
+            BinaryOp::And => "&&",
+            BinaryOp::Or => "||",
+        };
+        write!(f, "{op}")
+    }
+}
+
+impl Display for UnaryOp {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            UnaryOp::Neg => write!(f, "-"),
+            UnaryOp::Not => write!(f, "!"),
+        }
+    }
+}
+
+impl Display for Expr {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match &self.kind {
+            ExprKind::Integer(n) => write!(f, "{n}"),
+            ExprKind::Float(x) => write!(f, "{x:?}"),
+            ExprKind::Bool(b) => write!(f, "{b}"),
+            ExprKind::String(s) => write!(f, "{s:?}"),
+            ExprKind::Template(parts) => {
+                write!(f, "\"")?;
+                for part in parts {
+                    match &part.kind {
+                        ExprKind::String(text) => {
+                            write!(f, "{}", text.escape_debug().to_string().replace('{', "\\{"))?
+                        }
+                        _ => write!(f, "{{{part}}}")?,
+                    }
+                }
+                write!(f, "\"")
+            }
+            ExprKind::Name(name) => write!(f, "{name}"),
+            ExprKind::Lambda { params, body } => {
+                let params = params.iter().map(|p| &p.name).join(", ");
+                write!(f, "({params}) => {body}")
+            }
+            ExprKind::Call { callee, args } => {
+                write!(f, "{callee}({})", args.iter().join(", "))
+            }
+            ExprKind::Block(stmts) => write!(f, "{{ {} }}", stmts.iter().join("; ")),
+            ExprKind::Binary { op, lhs, rhs } => write!(f, "({lhs} {op} {rhs})"),
+            ExprKind::Unary { op, operand } => write!(f, "({op}{operand})"),
+            ExprKind::List(items) => write!(f, "[{}]", items.iter().join(", ")),
+            ExprKind::Map(entries) if entries.is_empty() => write!(f, "{{:}}"),
+            ExprKind::Map(entries) => {
+                let mut entries = entries.iter().map(|(k, v)| format!("{k}: {v}"));
+                write!(f, "{{{}}}", entries.join(", "))
+            }
+            ExprKind::Index { target, index } => write!(f, "{target}[{index}]"),
+            ExprKind::RecordType(fields) => {
+                write!(
+                    f,
+                    "record {{ {} }}",
+                    fields.iter().map(|n| &n.name).join(", ")
+                )
+            }
+            ExprKind::Construct { callee, fields } => {
+                write!(f, "{callee}({})", field_list(fields))
+            }
+            ExprKind::Field { target, field } => write!(f, "{target}.{}", field.name),
+            ExprKind::Update { target, fields } => {
+                write!(f, "{target} with {{ {} }}", field_list(fields))
+            }
+            ExprKind::Match { scrutinee, arms } => {
+                write!(f, "match {scrutinee} {{ {} }}", arms.iter().join(", "))
+            }
+            ExprKind::If {
+                cond,
+                then,
+                otherwise,
+            } => {
+                write!(f, "if {cond} {then}")?;
+                if let Some(otherwise) = otherwise {
+                    write!(f, " else {otherwise}")?;
+                }
+                Ok(())
+            }
+            ExprKind::While { label, cond, body } => {
+                write!(f, "{}while {cond} {body}", label_prefix(label))
+            }
+            ExprKind::For {
+                label,
+                names,
+                iterable,
+                end,
+                body,
+            } => {
+                let names = names.iter().map(|n| &n.name).join(", ");
+                write!(f, "{}for {names} in {iterable}", label_prefix(label))?;
+                if let Some(end) = end {
+                    write!(f, "..{end}")?;
+                }
+                write!(f, " {body}")
+            }
+            ExprKind::Break(label) => write!(f, "break{}", label_suffix(label)),
+            ExprKind::Continue(label) => write!(f, "continue{}", label_suffix(label)),
+        }
+    }
+}
+
+fn label_prefix(label: &Option<Name>) -> String {
+    label
+        .as_ref()
+        .map_or(String::new(), |label| format!("'{}: ", label.name))
+}
+
+fn label_suffix(label: &Option<Name>) -> String {
+    label
+        .as_ref()
+        .map_or(String::new(), |label| format!(" '{}", label.name))
+}
+
+// Formats named fields as `x: 1, y: 2`
+fn field_list(fields: &[(Name, Expr)]) -> String {
+    fields
+        .iter()
+        .map(|(name, value)| format!("{}: {value}", name.name))
+        .join(", ")
+}
+
+impl Display for Stmt {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Stmt::Let { name, value } => write!(f, "let {} = {value}", name.name),
+            Stmt::Binding { name, value } => write!(f, "{} = {value}", name.name),
+            Stmt::Union { name, variants } => {
+                write!(f, "{} = {}", name.name, variants.iter().join(" | "))
+            }
+            Stmt::Assign { target, value } => write!(f, "{target} = {value}"),
+            Stmt::Expr(expr) => write!(f, "{expr}"),
+        }
+    }
+}
+
+impl Display for Arm {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.pattern)?;
+        if let Some(guard) = &self.guard {
+            write!(f, " if {guard}")?;
+        }
+        write!(f, " => {}", self.body)
+    }
+}
+
+impl Display for Pattern {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match &self.kind {
+            PatternKind::Wildcard => write!(f, "_"),
+            PatternKind::Binding(name) => write!(f, "{name}"),
+            PatternKind::Integer(n) => write!(f, "{n}"),
+            PatternKind::Bool(b) => write!(f, "{b}"),
+            PatternKind::String(s) => write!(f, "{s:?}"),
+            PatternKind::Variant { name, fields } if fields.is_empty() => {
+                write!(f, "{}", name.name)
+            }
+            PatternKind::Variant { name, fields } => {
+                write!(f, "{}({})", name.name, fields.iter().join(", "))
+            }
+            PatternKind::List { items, rest } => {
+                let mut items = items.iter().map(|item| item.to_string()).collect_vec();
+                match rest.as_deref() {
+                    Some(Pattern {
+                        kind: PatternKind::Wildcard,
+                        ..
+                    }) => items.push("..".into()),
+                    Some(rest) => items.push(format!("..{rest}")),
+                    None => {}
+                }
+                write!(f, "[{}]", items.join(", "))
+            }
+        }
+    }
+}
+
+impl Display for Variant {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.name.name)?;
+        if !self.fields.is_empty() {
+            write!(f, "({})", self.fields.iter().map(|n| &n.name).join(", "))?;
+        }
+        Ok(())
+    }
+}
+
+impl Display for Program {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.stmts.iter().join("\n"))
+    }
+}
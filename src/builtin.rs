@@ -0,0 +1,195 @@
+use crate::{error::ErrorKind, object::Object, pool::ObjectPool, value::Value};
+
+/// A function implemented by the VM rather than compiled from a script. Names
+/// bound by a script shadow the builtin of the same name.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Builtin {
+    Len,
+    Slice,
+    Find,
+    Contains,
+    Upper,
+    Lower,
+    Split,
+    Push,
+    Pop,
+    Has,
+    Remove,
+    Keys,
+    Values,
+}
+
+impl Builtin {
+    pub fn from_name(name: &str) -> Option<Self> {
+        let builtin = match name {
+            "len" => Builtin::Len,
+            "slice" => Builtin::Slice,
+            "find" => Builtin::Find,
+            "contains" => Builtin::Contains,
+            "upper" => Builtin::Upper,
+            "lower" => Builtin::Lower,
+            "split" => Builtin::Split,
+            "push" => Builtin::Push,
+            "pop" => Builtin::Pop,
+            "has" => Builtin::Has,
+            "remove" => Builtin::Remove,
+            "keys" => Builtin::Keys,
+            "values" => Builtin::Values,
+            _ => return None,
+        };
+        Some(builtin)
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Builtin::Len => "len",
+            Builtin::Slice => "slice",
+            Builtin::Find => "find",
+            Builtin::Contains => "contains",
+            Builtin::Upper => "upper",
+            Builtin::Lower => "lower",
+            Builtin::Split => "split",
+            Builtin::Push => "push",
+            Builtin::Pop => "pop",
+            Builtin::Has => "has",
+            Builtin::Remove => "remove",
+            Builtin::Keys => "keys",
+            Builtin::Values => "values",
+        }
+    }
+
+    pub fn num_params(self) -> u32 {
+        match self {
+            Builtin::Len
+            | Builtin::Upper
+            | Builtin::Lower
+            | Builtin::Pop
+            | Builtin::Keys
+            | Builtin::Values => 1,
+            Builtin::Find
+            | Builtin::Contains
+            | Builtin::Split
+            | Builtin::Push
+            | Builtin::Has
+            | Builtin::Remove => 2,
+            Builtin::Slice => 3,
+        }
+    }
+
+    /// Applies the builtin to `args`, which the caller has checked there are
+    /// `num_params` of. Strings are indexed by character rather than by byte,
+    /// `len` and `slice` accept lists as well as strings, and `len` also
+    /// accepts maps.
+    pub fn call(self, pool: &mut ObjectPool, args: &[Value]) -> Result<Value, ErrorKind> {
+        let result = match self {
+            Builtin::Len => {
+                let len = match (pool.list(args[0]), pool.string(args[0])) {
+                    (Ok(list), _) => list.len(),
+                    (_, Ok(string)) => string.chars().count(),
+                    _ => match pool.map(args[0]) {
+                        Ok(map) => map.len(),
+                        Err(_) => return Err(not_a_sequence(pool, args[0])),
+                    },
+                };
+                Value::Integer(len as i64)
+            }
+            Builtin::Slice => {
+                let start = pool.integer(args[1])?;
+                let end = pool.integer(args[2])?;
+                let object = match (pool.list(args[0]), pool.string(args[0])) {
+                    (Ok(list), _) => {
+                        let (start, end) = slice_range(start, end, list.len())?;
+                        Object::List(list[start..end].to_vec())
+                    }
+                    (_, Ok(string)) => {
+                        let (start, end) = slice_range(start, end, string.chars().count())?;
+                        let slice = string.chars().skip(start).take(end - start).collect();
+                        Object::String(slice)
+                    }
+                    _ => return Err(not_a_sequence(pool, args[0])),
+                };
+                pool.allocate(object)
+            }
+            Builtin::Find => {
+                let string = pool.string(args[0])?;
+                let needle = pool.string(args[1])?;
+                match string.find(needle.as_str()) {
+                    Some(i) => Value::Integer(string[..i].chars().count() as i64),
+                    None => Value::Nil,
+                }
+            }
+            Builtin::Contains => {
+                let needle = pool.string(args[1])?;
+                Value::Bool(pool.string(args[0])?.contains(needle.as_str()))
+            }
+            Builtin::Upper => {
+                let upper = pool.string(args[0])?.to_uppercase();
+                pool.allocate(Object::String(upper))
+            }
+            Builtin::Lower => {
+                let lower = pool.string(args[0])?.to_lowercase();
+                pool.allocate(Object::String(lower))
+            }
+            Builtin::Split => {
+                let separator = pool.string(args[1])?;
+                if separator.is_empty() {
+                    return Err(ErrorKind::EmptySeparator);
+                }
+                let parts: Vec<String> = pool
+                    .string(args[0])?
+                    .split(separator.as_str())
+                    .map(String::from)
+                    .collect();
+                let items = parts
+                    .into_iter()
+                    .map(|part| pool.allocate(Object::String(part)))
+                    .collect();
+                pool.allocate(Object::List(items))
+            }
+            Builtin::Push => {
+                pool.list_mut(args[0])?.push(args[1]);
+                Value::Nil
+            }
+            Builtin::Pop => pool.list_mut(args[0])?.pop().ok_or(ErrorKind::EmptyList)?,
+            Builtin::Has => {
+                let key = pool.key(args[1])?;
+                Value::Bool(pool.map(args[0])?.get(&key).is_some())
+            }
+            Builtin::Remove => {
+                let key = pool.key(args[1])?;
+                match pool.map_mut(args[0])?.remove(&key) {
+                    Some(value) => value,
+                    None => {
+                        let key = pool.repr(&args[1]);
+                        return Err(ErrorKind::MissingKey { key });
+                    }
+                }
+            }
+            Builtin::Keys => {
+                let keys = pool.map(args[0])?.keys.clone();
+                pool.allocate(Object::List(keys))
+            }
+            Builtin::Values => {
+                let values = pool.map(args[0])?.values.clone();
+                pool.allocate(Object::List(values))
+            }
+        };
+        Ok(result)
+    }
+}
+
+// Where a builtin accepts either, the error names both
+fn not_a_sequence(pool: &ObjectPool, value: Value) -> ErrorKind {
+    ErrorKind::TypeMismatch {
+        expected: "string or list",
+        found: pool.type_name(&value),
+    }
+}
+
+// Checks that `start..end` lies within a sequence of length `len`.
+fn slice_range(start: i64, end: i64, len: usize) -> Result<(usize, usize), ErrorKind> {
+    if start < 0 || end < start || end as usize > len {
+        return Err(ErrorKind::SliceOutOfRange { start, end, len });
+    }
+    Ok((start as usize, end as usize))
+}
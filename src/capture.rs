@@ -0,0 +1,378 @@
+use std::collections::{HashMap, HashSet};
+
+use crate::{
+    ast::{Expr, ExprKind, Name, Pattern, PatternKind, Stmt},
+    token::SourceLocation,
+};
+
+/// How a program's bindings are used, with each binding identified by the
+/// offset of the name in its declaration.
+pub struct Bindings {
+    /// The program's top-level declarations, in order. These are kept in
+    /// globals rather than on the stack.
+    pub globals: Vec<Name>,
+    /// The names each lambda refers to that aren't bound by its parameters or
+    /// by bindings in its body, in order of first use, by the offset of the
+    /// lambda. These are the values a lambda may have to capture in its
+    /// closure when it's created.
+    pub free: HashMap<usize, Vec<String>>,
+    /// Bindings assigned to after they're declared.
+    pub assigned: HashSet<usize>,
+    /// Assigned bindings that are also captured by a lambda. These have to be
+    /// kept in upvalues shared by the closures that capture them, so every
+    /// closure sees every assignment.
+    pub shared: HashSet<usize>,
+}
+
+/// Works out how the bindings in a program are used. Names are resolved as the
+/// compiler resolves them, so a lambda can refer to a top-level binding that's
+/// declared after it.
+pub fn analyse_bindings(stmts: &[Stmt]) -> Bindings {
+    let mut globals: Vec<Name> = Vec::new();
+    for stmt in stmts {
+        match stmt {
+            Stmt::Let { name, .. } => globals.push(name.clone()),
+            // Assigning to a name that's already declared doesn't declare it
+            Stmt::Binding { name, .. } => {
+                if !globals.iter().any(|global| global.name == name.name) {
+                    globals.push(name.clone());
+                }
+            }
+            Stmt::Union { name, variants } => {
+                globals.extend(variants.iter().map(|variant| variant.name.clone()));
+                globals.push(name.clone());
+            }
+            Stmt::Assign { .. } | Stmt::Expr(_) => {}
+        }
+    }
+    let mut first = HashMap::new();
+    for global in &globals {
+        first
+            .entry(global.name.clone())
+            .or_insert(global.location.0.start);
+    }
+    let mut analysis = BindingAnalysis {
+        scope: Vec::new(),
+        top_level: globals
+            .iter()
+            .map(|global| global.location.0.start)
+            .collect(),
+        first,
+        depth: 0,
+        lambdas: Vec::new(),
+        free: HashMap::new(),
+        assigned: HashSet::new(),
+        captured: HashSet::new(),
+    };
+    analysis.stmts(stmts);
+    Bindings {
+        globals,
+        free: analysis.free,
+        shared: analysis
+            .assigned
+            .intersection(&analysis.captured)
+            .copied()
+            .collect(),
+        assigned: analysis.assigned,
+    }
+}
+
+struct BindingAnalysis {
+    // Each binding in scope, with its declaration and the depth of the lambda
+    // that declared it
+    scope: Vec<(String, usize, usize)>,
+    // The top-level declarations, which lambdas refer to without capturing
+    top_level: HashSet<usize>,
+    // The first top-level declaration of each name
+    first: HashMap<String, usize>,
+    // The number of lambdas enclosing the code being analysed
+    depth: usize,
+    // The offsets of those lambdas, outermost first
+    lambdas: Vec<usize>,
+    free: HashMap<usize, Vec<String>>,
+    assigned: HashSet<usize>,
+    captured: HashSet<usize>,
+}
+
+impl BindingAnalysis {
+    fn declare(&mut self, name: &str, location: &SourceLocation) {
+        self.scope.push((name.into(), location.0.start, self.depth));
+    }
+
+    // Adds a name to the free variables of the lambdas deeper than `depth`
+    // that enclose the code being analysed.
+    fn free(&mut self, name: &str, depth: usize) {
+        for lambda in &self.lambdas[depth..] {
+            let free = self.free.get_mut(lambda).unwrap();
+            if !free.iter().any(|free| free == name) {
+                free.push(name.into());
+            }
+        }
+    }
+
+    // Marks the binding in scope with a name as captured if it was declared
+    // outside the current lambda, returning its declaration.
+    fn in_scope(&mut self, name: &str) -> Option<usize> {
+        let &(_, declaration, depth) = self.scope.iter().rev().find(|(n, _, _)| n == name)?;
+        if depth < self.depth {
+            self.free(name, depth);
+            if !self.top_level.contains(&declaration) {
+                self.captured.insert(declaration);
+            }
+        }
+        Some(declaration)
+    }
+
+    // Returns the declaration of the binding a name refers to. Within a
+    // lambda, a name that isn't in scope yet may refer to a later top-level
+    // binding. Such names are free in every enclosing lambda, as are builtins
+    // and undefined names, which the compiler resolves.
+    fn name(&mut self, name: &str) -> Option<usize> {
+        if let Some(declaration) = self.in_scope(name) {
+            return Some(declaration);
+        }
+        self.free(name, 0);
+        self.first.get(name).copied().filter(|_| self.depth > 0)
+    }
+
+    // Assignment without `let` binds the name if it isn't in scope, just as
+    // the compiler does. Unlike a reference, it never refers to a later
+    // top-level binding.
+    fn assign(&mut self, name: &Name) {
+        match self.in_scope(&name.name) {
+            Some(declaration) => {
+                self.assigned.insert(declaration);
+            }
+            None => self.declare(&name.name, &name.location),
+        }
+    }
+
+    fn pattern(&mut self, pattern: &Pattern) {
+        match &pattern.kind {
+            PatternKind::Wildcard
+            | PatternKind::Integer(_)
+            | PatternKind::Bool(_)
+            | PatternKind::String(_) => {}
+            PatternKind::Binding(name) => self.declare(name, &pattern.location),
+            PatternKind::Variant { name, fields } => {
+                self.name(&name.name);
+                for field in fields {
+                    self.pattern(field);
+                }
+            }
+            PatternKind::List { items, rest } => {
+                for item in items.iter().chain(rest.as_deref()) {
+                    self.pattern(item);
+                }
+            }
+        }
+    }
+
+    fn stmts(&mut self, stmts: &[Stmt]) {
+        let len = self.scope.len();
+        for stmt in stmts {
+            match stmt {
+                Stmt::Let { name, value } => {
+                    self.expr(value);
+                    self.declare(&name.name, &name.location);
+                }
+                Stmt::Binding { name, value } => {
+                    self.expr(value);
+                    self.assign(name);
+                }
+                Stmt::Union { name, variants } => {
+                    for variant in variants {
+                        self.declare(&variant.name.name, &variant.name.location);
+                    }
+                    self.declare(&name.name, &name.location);
+                }
+                Stmt::Assign { target, value } => {
+                    self.expr(target);
+                    self.expr(value);
+                }
+                Stmt::Expr(expr) => self.expr(expr),
+            }
+        }
+        self.scope.truncate(len);
+    }
+
+    fn expr(&mut self, expr: &Expr) {
+        match &expr.kind {
+            ExprKind::Integer(_)
+            | ExprKind::Float(_)
+            | ExprKind::Bool(_)
+            | ExprKind::String(_)
+            | ExprKind::RecordType(_) => {}
+            ExprKind::Name(name) => {
+                self.name(name);
+            }
+            ExprKind::Lambda { params, body } => {
+                let len = self.scope.len();
+                self.depth += 1;
+                self.lambdas.push(expr.location.0.start);
+                self.free.entry(expr.location.0.start).or_default();
+                for param in params {
+                    self.declare(&param.name, &param.location);
+                }
+                self.expr(body);
+                self.lambdas.pop();
+                self.depth -= 1;
+                self.scope.truncate(len);
+            }
+            ExprKind::Call { callee, args } => {
+                self.expr(callee);
+                for arg in args {
+                    self.expr(arg);
+                }
+            }
+            ExprKind::Block(stmts) => self.stmts(stmts),
+            ExprKind::Binary { lhs, rhs, .. } => {
+                self.expr(lhs);
+                self.expr(rhs);
+            }
+            ExprKind::Unary { operand, .. } => self.expr(operand),
+            ExprKind::List(items) | ExprKind::Template(items) => {
+                for item in items {
+                    self.expr(item);
+                }
+            }
+            ExprKind::Map(entries) => {
+                for (key, value) in entries {
+                    self.expr(key);
+                    self.expr(value);
+                }
+            }
+            ExprKind::Construct {
+                callee: target,
+                fields,
+            }
+            | ExprKind::Update { target, fields } => {
+                self.expr(target);
+                for (_, value) in fields {
+                    self.expr(value);
+                }
+            }
+            ExprKind::Field { target, .. } => self.expr(target),
+            ExprKind::Match { scrutinee, arms } => {
+                self.expr(scrutinee);
+                for arm in arms {
+                    let len = self.scope.len();
+                    self.pattern(&arm.pattern);
+                    if let Some(guard) = &arm.guard {
+                        self.expr(guard);
+                    }
+                    self.expr(&arm.body);
+                    self.scope.truncate(len);
+                }
+            }
+            ExprKind::Index { target, index } => {
+                self.expr(target);
+                self.expr(index);
+            }
+            ExprKind::If {
+                cond,
+                then,
+                otherwise,
+            } => {
+                self.expr(cond);
+                self.expr(then);
+                if let Some(otherwise) = otherwise {
+                    self.expr(otherwise);
+                }
+            }
+            ExprKind::While { cond, body, .. } => {
+                self.expr(cond);
+                self.expr(body);
+            }
+            ExprKind::For {
+                names,
+                iterable,
+                end,
+                body,
+                ..
+            } => {
+                self.expr(iterable);
+                if let Some(end) = end {
+                    self.expr(end);
+                }
+                let len = self.scope.len();
+                for name in names {
+                    self.declare(&name.name, &name.location);
+                }
+                self.expr(body);
+                self.scope.truncate(len);
+            }
+            ExprKind::Break(_) | ExprKind::Continue(_) => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{parser::parse, token::Tokens};
+
+    // Returns the free variables of the lambda at the start of a source.
+    fn lambda_free_variables(source: &str) -> Vec<String> {
+        let tokens = Tokens::from_source(source).unwrap();
+        let program = parse(&tokens).unwrap();
+        analyse_bindings(&program.stmts).free.remove(&0).unwrap()
+    }
+
+    #[test]
+    fn test_free_variables() {
+        assert_eq!(lambda_free_variables("(x) => x + y"), ["y"]);
+        assert_eq!(
+            lambda_free_variables("(x) => { f(z, x) + (y) => y + z + w }"),
+            ["f", "z", "w"]
+        );
+        assert_eq!(
+            lambda_free_variables("() => {\n let a = b\n let b = a\n a + b + c\n}"),
+            ["b", "c"]
+        );
+        // Assigning to a name that isn't in scope binds it
+        assert_eq!(
+            lambda_free_variables("() => {\n a = b\n { let b = a }\n b\n}"),
+            ["b"]
+        );
+        // Assigning to one in an enclosing lambda captures it
+        assert_eq!(lambda_free_variables("(a) => () => { a = b }"), ["b"]);
+        let source = "() => {\n a = 1\n () => { a = 2\nc }\n}";
+        let tokens = Tokens::from_source(source).unwrap();
+        let program = parse(&tokens).unwrap();
+        let free = analyse_bindings(&program.stmts).free;
+        assert_eq!(free[&0], ["c"]);
+        assert_eq!(free[&16], ["a", "c"]);
+    }
+
+    fn shared(source: &str) -> Vec<usize> {
+        let tokens = Tokens::from_source(source).unwrap();
+        let program = parse(&tokens).unwrap();
+        let mut shared: Vec<usize> = analyse_bindings(&program.stmts)
+            .shared
+            .into_iter()
+            .collect();
+        shared.sort();
+        shared
+    }
+
+    #[test]
+    fn test_shared_bindings() {
+        // Only `x` is both captured and assigned
+        assert_eq!(
+            shared("() => {\nx = 1\ny = 2\nf = () => x + y\nx = 3\nz = 4\nz = 5\n}"),
+            [8]
+        );
+        // Assigned inside the closure, including a parameter of an outer one
+        assert_eq!(shared("(n) => () => { n = n + 1 }"), [1]);
+        // `let` makes a new binding, so neither is assigned to
+        assert_eq!(
+            shared("() => {\nx = 1\nf = () => x\nlet x = 2\n}"),
+            Vec::<usize>::new()
+        );
+        // Top-level bindings are globals, which closures don't capture
+        assert_eq!(shared("x = 1\nf = () => x\nx = 2"), Vec::<usize>::new());
+        // A closure's own bindings aren't captured
+        assert_eq!(shared("() => { x = 1\nx = 2 }"), Vec::<usize>::new());
+    }
+}
@@ -0,0 +1,2202 @@
+use std::collections::{HashMap, HashSet};
+
+use crate::{
+    ast::{self, BinaryOp, ExprKind, PatternKind, Stmt, UnaryOp},
+    builtin::Builtin,
+    capture::analyse_bindings,
+    diagnostic::{Diagnostic, Severity},
+    error::RuntimeError,
+    matching::{self, Access, Pat, Tag, Test, Tree},
+    record::Shape,
+    source_map::SourceMap,
+    token::SourceLocation,
+    vm::Expr,
+};
+
+type CompileResult<T> = Result<T, Diagnostic>;
+
+pub struct Program {
+    pub exprs: Vec<Expr>,
+    pub entry: usize,
+    /// String constants, referred to by `Expr::StringLiteral`.
+    pub strings: Vec<String>,
+    /// The source location each instruction was compiled from.
+    pub locations: Vec<SourceLocation>,
+    /// Function names by entry address.
+    pub names: HashMap<usize, String>,
+    pub warnings: Vec<Diagnostic>,
+}
+
+impl Program {
+    /// Describes a runtime error at the location of the instruction that
+    /// raised it, with a note for each call that was active, innermost first.
+    pub fn diagnostic(&self, map: &SourceMap, error: &RuntimeError) -> Diagnostic {
+        let mut diagnostic = Diagnostic {
+            severity: Severity::Error,
+            message: error.kind.to_string(),
+            location: self.locations.get(error.addr).cloned(),
+            notes: Vec::new(),
+        };
+        for frame in &error.backtrace {
+            let name = self
+                .names
+                .get(&frame.entry)
+                .map_or("<unknown>", |name| name);
+            diagnostic = diagnostic.with_note(match self.locations.get(frame.addr) {
+                Some(location) => format!("in {name} at {}", map.position(location.0.start)),
+                None => format!("in {name} at instruction {:04}", frame.addr),
+            });
+        }
+        diagnostic
+    }
+}
+
+/// Where the value of a binding is kept.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Place {
+    /// A slot of the current frame.
+    Local(usize),
+    /// A global, by index.
+    Global(usize),
+}
+
+// What's known at compile time about the value of a binding
+#[derive(Clone)]
+enum Known {
+    RecordType(Shape),
+    Record(Shape),
+    // A union variant's constructor, or for a variant without fields, its
+    // only value
+    Variant(Tag, Shape),
+}
+
+// The stack slots of a function call, named where they hold a parameter or a
+// binding. The function object and temporaries are unnamed. Slots are laid
+// out as described in `Thread::call`.
+struct Frame {
+    slots: Vec<Option<String>>,
+    // Slots holding an upvalue rather than the binding's value itself
+    upvalues: HashSet<usize>,
+    // The loops enclosing the code being compiled, innermost last
+    loops: Vec<Loop>,
+    // Slots holding a record or record type whose shape is known, so their
+    // fields can be accessed by slot rather than by name
+    known: HashMap<usize, Known>,
+}
+
+impl Frame {
+    fn new(slots: Vec<Option<String>>) -> Self {
+        Self {
+            slots,
+            upvalues: HashSet::new(),
+            loops: Vec::new(),
+            known: HashMap::new(),
+        }
+    }
+
+    fn truncate(&mut self, len: usize) {
+        self.slots.truncate(len);
+        self.known.retain(|&slot, _| slot < len);
+        self.upvalues.retain(|&slot| slot < len);
+    }
+}
+
+// A loop being compiled, for `break` and `continue` to branch out of
+struct Loop {
+    label: Option<String>,
+    // The number of slots at the start of each iteration, which `break` and
+    // `continue` pop the stack back down to
+    depth: usize,
+    // Branches to patch to the end of the loop and to its next iteration
+    breaks: Vec<usize>,
+    continues: Vec<usize>,
+}
+
+// The program's top-level bindings, which are kept in the VM's globals rather
+// than on the stack, so functions can refer to them wherever they're called
+struct Globals {
+    // The index of each top-level declaration, by the offset of its name
+    declared: HashMap<usize, usize>,
+    // The name of each global, by index
+    names: Vec<String>,
+    // The first global of each name. A function can refer to one before its
+    // definition, as long as it's only called once the definition has run.
+    first: HashMap<String, usize>,
+    // The globals defined so far, by name
+    defined: HashMap<String, usize>,
+    // Globals whose value is known. Only those never assigned to after their
+    // definition are, as a function may assign to one at any time.
+    known: HashMap<usize, Known>,
+}
+
+impl Globals {
+    fn new(declarations: &[ast::Name]) -> Self {
+        let mut first = HashMap::new();
+        for (index, name) in declarations.iter().enumerate() {
+            first.entry(name.name.clone()).or_insert(index);
+        }
+        Self {
+            declared: declarations
+                .iter()
+                .enumerate()
+                .map(|(index, name)| (name.location.0.start, index))
+                .collect(),
+            names: declarations.iter().map(|name| name.name.clone()).collect(),
+            first,
+            defined: HashMap::new(),
+            known: HashMap::new(),
+        }
+    }
+}
+
+struct Compiler {
+    exprs: Vec<Expr>,
+    strings: Vec<String>,
+    frames: Vec<Frame>,
+    locations: Vec<SourceLocation>,
+    names: HashMap<usize, String>,
+    warnings: Vec<Diagnostic>,
+    // The number of unions declared so far
+    unions: usize,
+    globals: Globals,
+    // The free variables of each lambda, by its offset
+    free: HashMap<usize, Vec<String>>,
+    // Bindings that need upvalues and bindings that are assigned to, as found
+    // by `analyse_bindings`
+    shared: HashSet<usize>,
+    assigned: HashSet<usize>,
+    // Whether the next expression compiled is in tail position, where its
+    // value is returned from the function as it is
+    tail: bool,
+    // The location of the expression being compiled
+    location: SourceLocation,
+}
+
+pub fn compile(program: &ast::Program) -> CompileResult<Program> {
+    let bindings = analyse_bindings(&program.stmts);
+    let mut compiler = Compiler {
+        exprs: Vec::new(),
+        strings: Vec::new(),
+        frames: vec![Frame::new(Vec::new())],
+        locations: Vec::new(),
+        names: HashMap::from([(0, "<main>".into())]),
+        warnings: Vec::new(),
+        unions: 0,
+        globals: Globals::new(&bindings.globals),
+        free: bindings.free,
+        shared: bindings.shared,
+        assigned: bindings.assigned,
+        tail: false,
+        location: SourceLocation(0..0),
+    };
+    compiler.block(&program.stmts, true, false)?;
+    compiler.emit(Expr::Return);
+    compiler.frames.pop();
+    Ok(Program {
+        exprs: compiler.exprs,
+        entry: 0,
+        strings: compiler.strings,
+        locations: compiler.locations,
+        names: compiler.names,
+        warnings: compiler.warnings,
+    })
+}
+
+impl Compiler {
+    fn frame(&mut self) -> &mut Frame {
+        self.frames.last_mut().unwrap()
+    }
+
+    // Appends an instruction, tracking the values it pops and pushes as slots
+    // of the current frame.
+    fn emit(&mut self, expr: Expr) -> usize {
+        let (pops, pushes) = expr.stack_effect();
+        let frame = self.frame();
+        frame.truncate(frame.slots.len() - pops);
+        frame.slots.extend(std::iter::repeat_n(None, pushes));
+        self.exprs.push(expr);
+        self.locations.push(self.location.clone());
+        self.exprs.len() - 1
+    }
+
+    // Points the branch at `addr` to the next instruction to be emitted.
+    fn patch(&mut self, addr: usize) {
+        self.patch_to(addr, self.exprs.len());
+    }
+
+    fn patch_to(&mut self, addr: usize, to: usize) {
+        match &mut self.exprs[addr] {
+            Expr::Branch { target } | Expr::BranchIfFalse { target } => *target = to,
+            _ => panic!("instruction at {addr} is not a branch"),
+        }
+    }
+
+    // Pops the stack down to `depth` slots before branching elsewhere. The
+    // code following the branch is unreachable from here, so the model of the
+    // stack is left as it is for it.
+    fn unwind(&mut self, depth: usize) {
+        for _ in depth..self.frame().slots.len() {
+            self.exprs.push(Expr::Pop);
+            self.locations.push(self.location.clone());
+        }
+    }
+
+    fn lookup(&self, name: &str) -> Option<usize> {
+        let frame = self.frames.last().unwrap();
+        frame
+            .slots
+            .iter()
+            .rposition(|slot| slot.as_deref() == Some(name))
+    }
+
+    // Finds the binding a name refers to, either in the current frame or
+    // among the globals defined so far. Within a function, it can also be a
+    // global that's defined later on.
+    fn resolve(&self, name: &str) -> Option<Place> {
+        if let Some(i) = self.lookup(name) {
+            return Some(Place::Local(i));
+        }
+        let globals = &self.globals;
+        let index = globals
+            .defined
+            .get(name)
+            .or_else(|| globals.first.get(name).filter(|_| self.frames.len() > 1))?;
+        Some(Place::Global(*index))
+    }
+
+    // Finds the binding an assignment to a name refers to. Unlike a reference,
+    // it's never a global that hasn't been defined yet, so assigning to a name
+    // in a function that's only defined later binds a local.
+    fn resolve_assignment(&self, name: &str) -> Option<Place> {
+        match self.lookup(name) {
+            Some(i) => Some(Place::Local(i)),
+            None => self.globals.defined.get(name).copied().map(Place::Global),
+        }
+    }
+
+    fn load(&mut self, place: Place) {
+        match place {
+            Place::Local(i) if self.frame().upvalues.contains(&i) => {
+                self.emit(Expr::LoadUpvalue { i });
+            }
+            Place::Local(i) => {
+                self.emit(Expr::Load { i });
+            }
+            Place::Global(index) => {
+                let name = self.globals.names[index].clone();
+                let name = self.string(&name);
+                self.emit(Expr::LoadGlobal { index, name });
+            }
+        }
+    }
+
+    fn known_at(&self, place: Place) -> Option<&Known> {
+        match place {
+            Place::Local(i) => self.frames.last().unwrap().known.get(&i),
+            Place::Global(index) => self.globals.known.get(&index),
+        }
+    }
+
+    // Moves a new binding into an upvalue if it's shared between closures.
+    // What's known about its value no longer holds, as any of them may assign
+    // to it.
+    fn declare(&mut self, slot: usize, location: &SourceLocation) {
+        if self.shared.contains(&location.0.start) {
+            self.emit(Expr::NewUpvalue { i: slot });
+            let frame = self.frame();
+            frame.upvalues.insert(slot);
+            frame.known.remove(&slot);
+        }
+    }
+
+    // Works out what an expression evaluates to without running it, where
+    // that's a record type or a record of a known shape.
+    fn known(&self, expr: &ast::Expr) -> Option<Known> {
+        match &expr.kind {
+            ExprKind::RecordType(fields) => Some(Known::RecordType(Shape {
+                name: String::new(),
+                fields: fields.iter().map(|field| field.name.clone()).collect(),
+            })),
+            ExprKind::Name(name) => {
+                let place = self.resolve(name)?;
+                match self.known_at(place)?.clone() {
+                    Known::Variant(_, shape) if shape.fields.is_empty() => {
+                        Some(Known::Record(shape))
+                    }
+                    Known::Variant(_, shape) => Some(Known::RecordType(shape)),
+                    known => Some(known),
+                }
+            }
+            ExprKind::Construct { callee, .. } => match self.known(callee)? {
+                Known::RecordType(shape) => Some(Known::Record(shape)),
+                _ => None,
+            },
+            ExprKind::Update { target, .. } => match self.known(target)? {
+                known @ Known::Record(_) => Some(known),
+                _ => None,
+            },
+            _ => None,
+        }
+    }
+
+    fn string(&mut self, string: &str) -> usize {
+        self.strings.push(string.into());
+        self.strings.len() - 1
+    }
+
+    // Binds a name to the value of an expression, in a new slot on top of the
+    // stack.
+    fn bind(&mut self, name: &ast::Name, value: &ast::Expr) -> CompileResult<usize> {
+        let known = self.known(value);
+        let slot = self.frame().slots.len();
+        self.named_expr(Some(&name.name), value)?;
+        if let Some(known) = known {
+            self.frame().known.insert(slot, known);
+        }
+        self.frame().slots[slot] = Some(name.name.clone());
+        self.declare(slot, &name.location);
+        Ok(slot)
+    }
+
+    // Assigns the value of an expression to the binding in `place`. Assigning
+    // doesn't count as using the binding.
+    fn assign(&mut self, place: Place, name: &ast::Name, value: &ast::Expr) -> CompileResult<()> {
+        let known = self.known(value);
+        self.named_expr(Some(&name.name), value)?;
+        let outer = std::mem::replace(&mut self.location, name.location.clone());
+        match place {
+            Place::Local(slot) if self.frame().upvalues.contains(&slot) => {
+                self.emit(Expr::StoreUpvalue { i: slot });
+            }
+            Place::Local(slot) => {
+                self.emit(Expr::Store { i: slot });
+                let frame = self.frame();
+                match known {
+                    Some(known) => frame.known.insert(slot, known),
+                    None => frame.known.remove(&slot),
+                };
+            }
+            Place::Global(index) => {
+                self.emit(Expr::StoreGlobal { index });
+                self.globals.known.remove(&index);
+            }
+        }
+        self.location = outer;
+        Ok(())
+    }
+
+    // Pops the value on top of the stack into the global declared by `name`,
+    // which comes into scope. Returns the global's index.
+    fn define(&mut self, name: &ast::Name, known: Option<Known>) -> usize {
+        let index = self.globals.declared[&name.location.0.start];
+        self.emit(Expr::StoreGlobal { index });
+        self.globals.defined.insert(name.name.clone(), index);
+        if let Some(known) = known.filter(|_| !self.assigned.contains(&name.location.0.start)) {
+            self.globals.known.insert(index, known);
+        }
+        index
+    }
+
+    // Declares the functions bound by the run of statements at the start of
+    // `stmts` ahead of time if any of them refers to one bound after it, so
+    // they can call each other. Each is bound to an upvalue holding nil until
+    // its statement assigns it. Returns the slots of the functions declared.
+    fn predeclare(&mut self, stmts: &[Stmt]) -> Vec<usize> {
+        let mut functions: Vec<(&ast::Name, &ast::Expr)> = Vec::new();
+        for stmt in stmts {
+            let (name, value) = match stmt {
+                Stmt::Let { name, value } => (name, value),
+                Stmt::Binding { name, value } if self.resolve_assignment(&name.name).is_none() => {
+                    (name, value)
+                }
+                _ => break,
+            };
+            if !matches!(value.kind, ExprKind::Lambda { .. }) {
+                break;
+            }
+            if functions.iter().any(|(other, _)| other.name == name.name) {
+                break;
+            }
+            functions.push((name, value));
+        }
+        let forward = functions.iter().enumerate().any(|(i, (_, value))| {
+            self.free[&value.location.0.start].iter().any(|free| {
+                functions[i + 1..]
+                    .iter()
+                    .any(|(name, _)| name.name == *free)
+            })
+        });
+        if !forward {
+            return Vec::new();
+        }
+        let mut slots = Vec::new();
+        for (name, _) in functions {
+            let slot = self.frame().slots.len();
+            self.emit(Expr::Nil);
+            self.emit(Expr::NewUpvalue { i: slot });
+            let frame = self.frame();
+            frame.slots[slot] = Some(name.name.clone());
+            frame.upvalues.insert(slot);
+            slots.push(slot);
+        }
+        slots
+    }
+
+    // Compiles a sequence of statements, leaving the value of the last one on
+    // the stack. Bindings stay on the stack as slots of the current frame
+    // until the end of the block, when they're popped from beneath its value.
+    // A function body's bindings are left for `Return` to pop instead, and
+    // the program's own are globals.
+    fn block(&mut self, stmts: &[Stmt], top_level: bool, tail: bool) -> CompileResult<()> {
+        let global = top_level && self.frames.len() == 1;
+        let depth = self.frame().slots.len();
+        if stmts.is_empty() {
+            self.emit(Expr::Nil);
+        }
+        // The slots of functions declared ahead of their statements, by the
+        // index of the statement. Globals don't need to be.
+        let mut declared = HashMap::new();
+        for (i, stmt) in stmts.iter().enumerate() {
+            let last = i + 1 == stmts.len();
+            if !global && !declared.contains_key(&i) {
+                let slots = self.predeclare(&stmts[i..]);
+                declared = (i..).zip(slots).collect();
+            }
+            match stmt {
+                Stmt::Let { name, value } | Stmt::Binding { name, value }
+                    if declared.contains_key(&i) =>
+                {
+                    let slot = declared[&i];
+                    self.named_expr(Some(&name.name), value)?;
+                    self.emit(Expr::StoreUpvalue { i: slot });
+                    if last {
+                        self.load(Place::Local(slot));
+                    }
+                }
+                Stmt::Let { name, value } | Stmt::Binding { name, value }
+                    if global && self.globals.declared.contains_key(&name.location.0.start) =>
+                {
+                    let known = self.known(value);
+                    self.named_expr(Some(&name.name), value)?;
+                    let index = self.define(name, known);
+                    if last {
+                        self.load(Place::Global(index));
+                    }
+                }
+                Stmt::Let { name, value } => {
+                    let slot = self.bind(name, value)?;
+                    if last {
+                        self.load(Place::Local(slot));
+                    }
+                }
+                Stmt::Binding { name, value } => {
+                    let place = match self.resolve_assignment(&name.name) {
+                        Some(place) => {
+                            self.assign(place, name, value)?;
+                            place
+                        }
+                        None => Place::Local(self.bind(name, value)?),
+                    };
+                    if last {
+                        self.load(place);
+                    }
+                }
+                Stmt::Union { name, variants } => {
+                    let slot = self.union(name, variants)?;
+                    let place = match global {
+                        true => Place::Global(self.define_union(name, variants)),
+                        false => Place::Local(slot),
+                    };
+                    if last {
+                        self.load(place);
+                    }
+                }
+                Stmt::Assign { target, value } => {
+                    let ExprKind::Index {
+                        target: list,
+                        index,
+                    } = &target.kind
+                    else {
+                        unreachable!("the parser only allows assigning to an index");
+                    };
+                    self.expr(list)?;
+                    self.expr(index)?;
+                    self.expr(value)?;
+                    let outer = std::mem::replace(&mut self.location, target.location.clone());
+                    self.emit(Expr::SetIndex);
+                    self.location = outer;
+                    if last {
+                        self.emit(Expr::Nil);
+                    }
+                }
+                Stmt::Expr(expr) => {
+                    self.tail = tail && last;
+                    self.expr(expr)?;
+                    if !last {
+                        self.emit(Expr::Pop);
+                    }
+                }
+            }
+        }
+        let count = self.frame().slots.len() - depth - 1;
+        if !top_level && count > 0 {
+            self.emit(Expr::PopBelow {
+                count: count as u32,
+            });
+        }
+        Ok(())
+    }
+
+    // Compiles a lambda. If it's bound to a name, that name refers to the
+    // function object within its body, unless a parameter shadows it.
+    fn lambda(
+        &mut self,
+        name: Option<&str>,
+        params: &[ast::Name],
+        body: &ast::Expr,
+    ) -> CompileResult<()> {
+        let mut slots = Vec::new();
+        for param in params {
+            if slots.contains(&Some(param.name.clone())) {
+                return Err(Diagnostic::error(
+                    format!("duplicate parameter '{}'", param.name),
+                    param.location.clone(),
+                ));
+            }
+            slots.push(Some(param.name.clone()));
+        }
+        // The function object itself
+        let name = name.filter(|name| !slots.contains(&Some(name.to_string())));
+        slots.push(name.map(String::from));
+        // Captured values follow the function object. Globals aren't captured,
+        // and names that can't be resolved here are left out so the body
+        // reports them as undefined. Upvalues are captured as they are, so the
+        // closure shares them.
+        let mut known = HashMap::new();
+        let mut upvalues = HashSet::new();
+        // The lambda being compiled is the current expression
+        let captures: Vec<usize> = self.free[&self.location.0.start]
+            .clone()
+            .into_iter()
+            .filter(|free| Some(free.as_str()) != name)
+            .filter_map(|name| {
+                let Place::Local(i) = self.resolve(&name)? else {
+                    return None;
+                };
+                if let Some(shape) = self.frame().known.get(&i) {
+                    known.insert(slots.len(), shape.clone());
+                }
+                if self.frame().upvalues.contains(&i) {
+                    upvalues.insert(slots.len());
+                }
+                slots.push(Some(name));
+                Some(i)
+            })
+            .collect();
+        let skip = self.emit(Expr::Branch { target: 0 });
+        let entry = self.exprs.len();
+        self.names.insert(entry, name.unwrap_or("<lambda>").into());
+        self.frames.push(Frame {
+            upvalues,
+            known,
+            ..Frame::new(slots)
+        });
+        for (i, param) in params.iter().enumerate() {
+            self.declare(i, &param.location);
+        }
+        match &body.kind {
+            ExprKind::Block(stmts) => self.block(stmts, true, true)?,
+            _ => {
+                self.tail = true;
+                self.expr(body)?;
+            }
+        }
+        self.emit(Expr::Return);
+        self.frames.pop();
+        self.patch(skip);
+        for &i in &captures {
+            self.emit(Expr::Load { i });
+        }
+        self.emit(Expr::Function {
+            entry,
+            closure_len: captures.len() as u32,
+            num_params: params.len() as u32,
+        });
+        Ok(())
+    }
+
+    // Compiles `&&` and `||`, which only evaluate their right hand side if the
+    // left doesn't decide the result. Both operands must be booleans, so
+    // neither is in tail position: the right hand side is checked after it's
+    // evaluated.
+    fn logical(&mut self, op: BinaryOp, lhs: &ast::Expr, rhs: &ast::Expr) -> CompileResult<()> {
+        // The result if either operand short-circuits
+        let decided = matches!(op, BinaryOp::Or);
+        let mut branches = Vec::new();
+        for operand in [lhs, rhs] {
+            self.expr(operand)?;
+            if decided {
+                self.emit(Expr::Not);
+            }
+            branches.push(self.emit(Expr::BranchIfFalse { target: 0 }));
+        }
+        let depth = self.frame().slots.len();
+        self.emit(Expr::BoolLiteral { boolean: !decided });
+        let end = self.emit(Expr::Branch { target: 0 });
+        for branch in branches {
+            self.patch(branch);
+        }
+        self.frame().truncate(depth);
+        self.emit(Expr::BoolLiteral { boolean: decided });
+        self.patch(end);
+        Ok(())
+    }
+
+    // Compiles an `if` expression. Without an `else` it evaluates to nil when
+    // the condition is false.
+    fn if_expr(
+        &mut self,
+        cond: &ast::Expr,
+        then: &ast::Expr,
+        otherwise: Option<&ast::Expr>,
+        tail: bool,
+    ) -> CompileResult<()> {
+        self.expr(cond)?;
+        // Blame the condition if it isn't a boolean
+        let outer = std::mem::replace(&mut self.location, cond.location.clone());
+        let skip_then = self.emit(Expr::BranchIfFalse { target: 0 });
+        self.location = outer;
+        let depth = self.frame().slots.len();
+        self.tail = tail;
+        self.expr(then)?;
+        let end = self.emit(Expr::Branch { target: 0 });
+        self.patch(skip_then);
+        self.frame().truncate(depth);
+        match otherwise {
+            Some(otherwise) => {
+                self.tail = tail;
+                self.expr(otherwise)?
+            }
+            None => {
+                self.emit(Expr::Nil);
+            }
+        }
+        self.patch(end);
+        Ok(())
+    }
+
+    // Compiles a loop's body, discarding its value, and returns the loop with
+    // the branches out of it that need patching.
+    fn loop_body(
+        &mut self,
+        label: Option<&ast::Name>,
+        depth: usize,
+        body: &ast::Expr,
+    ) -> CompileResult<Loop> {
+        self.frame().loops.push(Loop {
+            label: label.map(|label| label.name.clone()),
+            depth,
+            breaks: Vec::new(),
+            continues: Vec::new(),
+        });
+        let result = self.expr(body);
+        let compiled = self.frame().loops.pop().unwrap();
+        result?;
+        self.emit(Expr::Pop);
+        Ok(compiled)
+    }
+
+    fn while_expr(
+        &mut self,
+        label: Option<&ast::Name>,
+        cond: &ast::Expr,
+        body: &ast::Expr,
+    ) -> CompileResult<()> {
+        let depth = self.frame().slots.len();
+        let head = self.exprs.len();
+        self.expr(cond)?;
+        let exit = self.emit(Expr::BranchIfFalse { target: 0 });
+        let compiled = self.loop_body(label, depth, body)?;
+        for branch in compiled.continues {
+            self.patch_to(branch, head);
+        }
+        self.emit(Expr::Branch { target: head });
+        self.patch(exit);
+        for branch in compiled.breaks {
+            self.patch(branch);
+        }
+        self.emit(Expr::Nil);
+        Ok(())
+    }
+
+    // Compiles a `for` loop, which keeps two values beneath the names it
+    // binds: the list or map and the position in it, or for a range the
+    // counter and the end.
+    fn for_expr(
+        &mut self,
+        label: Option<&ast::Name>,
+        names: &[ast::Name],
+        iterable: &ast::Expr,
+        end: Option<&ast::Expr>,
+        body: &ast::Expr,
+    ) -> CompileResult<()> {
+        if let [first, second] = names {
+            if end.is_some() {
+                return Err(Diagnostic::error(
+                    "a loop over a range binds a single name",
+                    second.location.clone(),
+                ));
+            }
+            if first.name == second.name {
+                return Err(Diagnostic::error(
+                    format!("duplicate binding '{}'", second.name),
+                    second.location.clone(),
+                ));
+            }
+        }
+        let depth = self.frame().slots.len();
+        self.expr(iterable)?;
+        let (counter, items) = match end {
+            Some(end) => {
+                self.expr(end)?;
+                (depth, None)
+            }
+            None => {
+                self.emit(Expr::Literal { integer: 0 });
+                (depth + 1, Some(depth))
+            }
+        };
+        let head = self.exprs.len();
+        self.emit(Expr::Load { i: counter });
+        match items {
+            Some(items) => {
+                self.emit(Expr::Load { i: items });
+                self.emit(Expr::IterLen);
+            }
+            None => {
+                self.emit(Expr::Load { i: depth + 1 });
+            }
+        }
+        self.emit(Expr::Lt);
+        let exit = self.emit(Expr::BranchIfFalse { target: 0 });
+        match items {
+            Some(items) => {
+                let parts: &[Expr] = match names.len() {
+                    1 => &[Expr::IterItem],
+                    _ => &[Expr::IterKey, Expr::IterValue],
+                };
+                for &part in parts {
+                    self.emit(Expr::Load { i: items });
+                    self.emit(Expr::Load { i: counter });
+                    self.emit(part);
+                }
+            }
+            None => {
+                self.emit(Expr::Load { i: counter });
+            }
+        }
+        let state = depth + 2;
+        for (i, name) in names.iter().enumerate() {
+            let frame = self.frame();
+            frame.slots[state + i] = Some(name.name.clone());
+            self.declare(state + i, &name.location);
+        }
+        let compiled = self.loop_body(label, state, body)?;
+        for _ in names {
+            self.emit(Expr::Pop);
+        }
+        for branch in compiled.continues {
+            self.patch(branch);
+        }
+        self.emit(Expr::Load { i: counter });
+        self.emit(Expr::Literal { integer: 1 });
+        self.emit(Expr::Add);
+        self.emit(Expr::Store { i: counter });
+        self.emit(Expr::Branch { target: head });
+        self.patch(exit);
+        for branch in compiled.breaks {
+            self.patch(branch);
+        }
+        self.emit(Expr::Nil);
+        self.emit(Expr::PopBelow { count: 2 });
+        Ok(())
+    }
+
+    // Compiles `break` or `continue`, which branches out of the loop with the
+    // given label, or the innermost loop.
+    fn jump(&mut self, label: Option<&ast::Name>, is_break: bool) -> CompileResult<()> {
+        let keyword = if is_break { "break" } else { "continue" };
+        let loops = &self.frames.last().unwrap().loops;
+        let found = match label {
+            Some(label) => loops
+                .iter()
+                .rposition(|l| l.label.as_ref() == Some(&label.name))
+                .ok_or_else(|| {
+                    Diagnostic::error(
+                        format!("no enclosing loop labelled '{}'", label.name),
+                        label.location.clone(),
+                    )
+                })?,
+            None => loops.len().checked_sub(1).ok_or_else(|| {
+                Diagnostic::error(
+                    format!("{keyword} outside of a loop"),
+                    self.location.clone(),
+                )
+            })?,
+        };
+        self.unwind(loops[found].depth);
+        let branch = self.emit(Expr::Branch { target: 0 });
+        let target = &mut self.frame().loops[found];
+        if is_break {
+            target.breaks.push(branch);
+        } else {
+            target.continues.push(branch);
+        }
+        // Code after the jump expects it to have left a value
+        self.frame().slots.push(None);
+        Ok(())
+    }
+
+    // Compiles a union declaration, binding each variant to its constructor
+    // or, if it has no fields, its only value. The union itself is bound to a
+    // record with a field for each variant. Returns the union's slot.
+    fn union(&mut self, name: &ast::Name, variants: &[ast::Variant]) -> CompileResult<usize> {
+        let union = self.unions;
+        self.unions += 1;
+        let mut slots = Vec::new();
+        for (index, variant) in variants.iter().enumerate() {
+            if variants[..index]
+                .iter()
+                .any(|other| other.name.name == variant.name.name)
+            {
+                return Err(Diagnostic::error(
+                    format!("duplicate variant '{}'", variant.name.name),
+                    variant.name.location.clone(),
+                ));
+            }
+            let slot = self.frame().slots.len();
+            self.record_type(&variant.name.name, &variant.fields)?;
+            if variant.fields.is_empty() {
+                self.emit(Expr::NewRecord { len: 0 });
+            }
+            let tag = Tag {
+                union,
+                index,
+                count: variants.len(),
+            };
+            let shape = Shape {
+                name: variant.name.name.clone(),
+                fields: variant.fields.iter().map(|f| f.name.clone()).collect(),
+            };
+            let frame = self.frame();
+            frame.slots[slot] = Some(variant.name.name.clone());
+            frame.known.insert(slot, Known::Variant(tag, shape));
+            slots.push(slot);
+        }
+        let slot = self.frame().slots.len();
+        let names: Vec<ast::Name> = variants.iter().map(|v| v.name.clone()).collect();
+        self.record_type(&name.name, &names)?;
+        self.emit(Expr::NewRecord {
+            len: variants.len() as u32,
+        });
+        for (field, &i) in slots.iter().enumerate() {
+            self.emit(Expr::Load { i });
+            self.emit(Expr::SetField { slot: field as u32 });
+        }
+        self.frame().slots[slot] = Some(name.name.clone());
+        for (variant, &i) in variants.iter().zip(&slots) {
+            self.declare(i, &variant.name.location);
+        }
+        self.declare(slot, &name.location);
+        Ok(slot)
+    }
+
+    // Moves a top-level union and its variants from the stack into globals,
+    // returning the union's index.
+    fn define_union(&mut self, name: &ast::Name, variants: &[ast::Variant]) -> usize {
+        let index = self.define(name, None);
+        for variant in variants.iter().rev() {
+            let slot = self.frame().slots.len() - 1;
+            let known = self.frame().known.get(&slot).cloned();
+            self.define(&variant.name, known);
+        }
+        index
+    }
+
+    // Resolves the variants in a pattern, checking that it binds each name at
+    // most once.
+    fn pattern(&mut self, pattern: &ast::Pattern, bound: &mut Vec<String>) -> CompileResult<Pat> {
+        let pat = match &pattern.kind {
+            PatternKind::Wildcard => Pat::Any(None),
+            PatternKind::Binding(name) => {
+                if bound.contains(name) {
+                    return Err(Diagnostic::error(
+                        format!("duplicate binding '{name}'"),
+                        pattern.location.clone(),
+                    ));
+                }
+                bound.push(name.clone());
+                Pat::Any(Some((name.clone(), pattern.location.clone())))
+            }
+            PatternKind::Integer(n) => Pat::Test(Test::Integer(*n), Vec::new()),
+            PatternKind::Bool(b) => Pat::Test(Test::Bool(*b), Vec::new()),
+            PatternKind::String(s) => Pat::Test(Test::String(s.clone()), Vec::new()),
+            PatternKind::Variant { name, fields } => {
+                let variant =
+                    self.resolve(&name.name)
+                        .and_then(|place| match self.known_at(place) {
+                            Some(Known::Variant(tag, shape)) => {
+                                Some((place, *tag, shape.fields.len()))
+                            }
+                            _ => None,
+                        });
+                let Some((place, tag, len)) = variant else {
+                    return Err(Diagnostic::error(
+                        format!("unknown variant '{}'", name.name),
+                        name.location.clone(),
+                    ));
+                };
+                if fields.len() != len {
+                    return Err(Diagnostic::error(
+                        format!(
+                            "variant '{}' has {len} fields but the pattern has {}",
+                            name.name,
+                            fields.len()
+                        ),
+                        pattern.location.clone(),
+                    ));
+                }
+                let fields = fields
+                    .iter()
+                    .map(|field| self.pattern(field, bound))
+                    .collect::<CompileResult<_>>()?;
+                Pat::Test(Test::Variant { tag, place }, fields)
+            }
+            PatternKind::List { items, rest } => {
+                let mut parts = items
+                    .iter()
+                    .map(|item| self.pattern(item, bound))
+                    .collect::<CompileResult<Vec<_>>>()?;
+                if let Some(rest) = rest {
+                    parts.push(self.pattern(rest, bound)?);
+                }
+                let test = Test::List {
+                    len: items.len() as u32,
+                    rest: rest.is_some(),
+                };
+                Pat::Test(test, parts)
+            }
+        };
+        Ok(pat)
+    }
+
+    // Compiles a match by building a decision tree for its arms. The matched
+    // value stays on the stack until the arm's result replaces it.
+    fn match_expr(
+        &mut self,
+        scrutinee: &ast::Expr,
+        arms: &[ast::Arm],
+        tail: bool,
+    ) -> CompileResult<()> {
+        self.expr(scrutinee)?;
+        let root = self.frame().slots.len() - 1;
+        let mut patterns = Vec::new();
+        for arm in arms {
+            patterns.push(self.pattern(&arm.pattern, &mut Vec::new())?);
+        }
+        let guarded = arms.iter().map(|arm| arm.guard.is_some()).collect();
+        let decisions = matching::decide(patterns, guarded);
+        if !decisions.exhaustive {
+            self.warnings.push(
+                Diagnostic::warning("match doesn't cover every value", self.location.clone())
+                    .with_note("add a '_' arm to match anything else"),
+            );
+        }
+        for (arm, reachable) in arms.iter().zip(decisions.reachable) {
+            if !reachable {
+                self.warnings.push(Diagnostic::warning(
+                    "unreachable match arm",
+                    arm.pattern.location.clone(),
+                ));
+            }
+        }
+        let mut ends = Vec::new();
+        self.tree(&decisions.tree, root, arms, &mut ends, tail)?;
+        for end in ends {
+            self.patch(end);
+        }
+        self.frame().truncate(root + 1);
+        self.frame().slots.push(None);
+        self.emit(Expr::PopBelow { count: 1 });
+        Ok(())
+    }
+
+    // Compiles a decision tree for the value in slot `root`. Each leaf leaves
+    // its arm's result on the stack and branches to the end of the match,
+    // adding the branch to `ends`.
+    fn tree(
+        &mut self,
+        tree: &Tree,
+        root: usize,
+        arms: &[ast::Arm],
+        ends: &mut Vec<usize>,
+        tail: bool,
+    ) -> CompileResult<()> {
+        match tree {
+            Tree::Fail => {
+                self.emit(Expr::Load { i: root });
+                self.emit(Expr::NoMatch);
+            }
+            Tree::Test {
+                path,
+                test,
+                then,
+                otherwise,
+            } => {
+                self.load_part(root, path);
+                match test {
+                    Test::Variant { place, .. } => {
+                        self.load(*place);
+                        self.emit(Expr::TestVariant);
+                    }
+                    Test::Integer(integer) => {
+                        self.emit(Expr::Literal { integer: *integer });
+                        self.emit(Expr::Eq);
+                    }
+                    Test::Bool(boolean) => {
+                        self.emit(Expr::BoolLiteral { boolean: *boolean });
+                        self.emit(Expr::Eq);
+                    }
+                    Test::String(string) => {
+                        let index = self.string(string);
+                        self.emit(Expr::StringLiteral { index });
+                        self.emit(Expr::Eq);
+                    }
+                    Test::List { len, rest } => {
+                        self.emit(Expr::TestList {
+                            len: *len,
+                            rest: *rest,
+                        });
+                    }
+                }
+                let skip = self.emit(Expr::BranchIfFalse { target: 0 });
+                self.tree(then, root, arms, ends, tail)?;
+                self.patch(skip);
+                self.tree(otherwise, root, arms, ends, tail)?;
+            }
+            Tree::Leaf {
+                arm,
+                bindings,
+                fallback,
+            } => {
+                let arm = &arms[*arm];
+                let base = self.frame().slots.len();
+                for binding in bindings {
+                    self.load_part(root, &binding.path);
+                    let frame = self.frame();
+                    let slot = frame.slots.len() - 1;
+                    frame.slots[slot] = Some(binding.name.clone());
+                    self.declare(slot, &binding.location);
+                }
+                let guard = match &arm.guard {
+                    Some(guard) => {
+                        self.expr(guard)?;
+                        let outer = std::mem::replace(&mut self.location, guard.location.clone());
+                        let skip = self.emit(Expr::BranchIfFalse { target: 0 });
+                        self.location = outer;
+                        Some(skip)
+                    }
+                    None => None,
+                };
+                self.tail = tail;
+                self.expr(&arm.body)?;
+                if !bindings.is_empty() {
+                    self.emit(Expr::PopBelow {
+                        count: bindings.len() as u32,
+                    });
+                }
+                ends.push(self.emit(Expr::Branch { target: 0 }));
+                self.frame().truncate(base);
+                if let (Some(skip), Some(fallback)) = (guard, fallback) {
+                    self.patch(skip);
+                    for _ in bindings {
+                        self.frame().slots.push(None);
+                        self.emit(Expr::Pop);
+                    }
+                    self.tree(fallback, root, arms, ends, tail)?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    // Pushes the part of the value in slot `root` at the end of `path`.
+    fn load_part(&mut self, root: usize, path: &[Access]) {
+        self.emit(Expr::Load { i: root });
+        for access in path {
+            match *access {
+                Access::Field(slot) => {
+                    self.emit(Expr::GetField { slot });
+                }
+                Access::Item(integer) => {
+                    self.emit(Expr::Literal {
+                        integer: integer as i64,
+                    });
+                    self.emit(Expr::Index);
+                }
+                Access::Rest(start) => {
+                    self.emit(Expr::ListFrom { start });
+                }
+            }
+        }
+    }
+
+    // Compiles `record { ... }`, with field names stored as string constants.
+    fn record_type(&mut self, name: &str, fields: &[ast::Name]) -> CompileResult<()> {
+        for (i, field) in fields.iter().enumerate() {
+            if fields[..i].iter().any(|other| other.name == field.name) {
+                return Err(Diagnostic::error(
+                    format!("duplicate field '{}'", field.name),
+                    field.location.clone(),
+                ));
+            }
+        }
+        let name = self.string(name);
+        let start = self.strings.len();
+        for field in fields {
+            self.string(&field.name);
+        }
+        self.emit(Expr::Shape {
+            name,
+            fields: start,
+            len: fields.len() as u32,
+        });
+        Ok(())
+    }
+
+    // Compiles the values of named fields, storing each in the record on top
+    // of the stack. Fields are stored by slot if the record's shape is known
+    // and by name otherwise.
+    fn set_fields(
+        &mut self,
+        shape: Option<&Shape>,
+        fields: &[(ast::Name, ast::Expr)],
+    ) -> CompileResult<()> {
+        for (i, (field, value)) in fields.iter().enumerate() {
+            if fields[..i]
+                .iter()
+                .any(|(other, _)| other.name == field.name)
+            {
+                return Err(Diagnostic::error(
+                    format!("duplicate field '{}'", field.name),
+                    field.location.clone(),
+                ));
+            }
+            self.expr(value)?;
+            let outer = std::mem::replace(&mut self.location, field.location.clone());
+            match shape {
+                Some(shape) => {
+                    let slot = self.slot(shape, field)?;
+                    self.emit(Expr::SetField { slot });
+                }
+                None => {
+                    let name = self.string(&field.name);
+                    self.emit(Expr::SetNamedField { name });
+                }
+            }
+            self.location = outer;
+        }
+        Ok(())
+    }
+
+    fn slot(&self, shape: &Shape, field: &ast::Name) -> CompileResult<u32> {
+        match shape.slot(&field.name) {
+            Some(slot) => Ok(slot as u32),
+            None => Err(Diagnostic::error(
+                format!("record has no field '{}'", field.name),
+                field.location.clone(),
+            )),
+        }
+    }
+
+    fn construct(
+        &mut self,
+        callee: &ast::Expr,
+        fields: &[(ast::Name, ast::Expr)],
+    ) -> CompileResult<()> {
+        let shape = match self.known(callee) {
+            Some(Known::RecordType(shape)) => Some(shape),
+            _ => None,
+        };
+        if let Some(shape) = &shape {
+            let missing = shape
+                .fields
+                .iter()
+                .find(|name| !fields.iter().any(|(field, _)| &field.name == *name));
+            if let Some(missing) = missing {
+                return Err(Diagnostic::error(
+                    format!("missing field '{missing}'"),
+                    self.location.clone(),
+                ));
+            }
+        }
+        self.expr(callee)?;
+        self.emit(Expr::NewRecord {
+            len: fields.len() as u32,
+        });
+        self.set_fields(shape.as_ref(), fields)
+    }
+
+    fn expr(&mut self, expr: &ast::Expr) -> CompileResult<()> {
+        self.named_expr(None, expr)
+    }
+
+    // Compiles an expression, giving it `name` if it's a lambda or record
+    // type.
+    fn named_expr(&mut self, name: Option<&str>, expr: &ast::Expr) -> CompileResult<()> {
+        let outer = std::mem::replace(&mut self.location, expr.location.clone());
+        self.expr_kind(name, expr)?;
+        self.location = outer;
+        Ok(())
+    }
+
+    fn expr_kind(&mut self, name: Option<&str>, expr: &ast::Expr) -> CompileResult<()> {
+        let tail = std::mem::take(&mut self.tail);
+        match &expr.kind {
+            ExprKind::Integer(integer) => {
+                self.emit(Expr::Literal { integer: *integer });
+            }
+            ExprKind::Float(float) => {
+                self.emit(Expr::FloatLiteral { float: *float });
+            }
+            ExprKind::Bool(boolean) => {
+                self.emit(Expr::BoolLiteral { boolean: *boolean });
+            }
+            ExprKind::If {
+                cond,
+                then,
+                otherwise,
+            } => self.if_expr(cond, then, otherwise.as_deref(), tail)?,
+            ExprKind::String(string) => {
+                let index = self.string(string);
+                self.emit(Expr::StringLiteral { index });
+            }
+            ExprKind::List(items) => {
+                for item in items {
+                    self.expr(item)?;
+                }
+                self.emit(Expr::List {
+                    len: items.len() as u32,
+                });
+            }
+            ExprKind::Map(entries) => {
+                for (key, value) in entries {
+                    self.expr(key)?;
+                    self.expr(value)?;
+                }
+                self.emit(Expr::Map {
+                    len: entries.len() as u32,
+                });
+            }
+            ExprKind::Index { target, index } => {
+                self.expr(target)?;
+                self.expr(index)?;
+                self.emit(Expr::Index);
+            }
+            ExprKind::Template(parts) => {
+                for part in parts {
+                    self.expr(part)?;
+                }
+                self.emit(Expr::Concat {
+                    count: parts.len() as u32,
+                });
+            }
+            ExprKind::Name(name) => {
+                let Some(place) = self.resolve(name) else {
+                    if let Some(builtin) = Builtin::from_name(name) {
+                        self.emit(Expr::Builtin { builtin });
+                        return Ok(());
+                    }
+                    return Err(Diagnostic::error(
+                        format!("undefined name '{name}'"),
+                        expr.location.clone(),
+                    ));
+                };
+                self.load(place);
+            }
+            ExprKind::Lambda { params, body } => self.lambda(name, params, body)?,
+            ExprKind::RecordType(fields) => self.record_type(name.unwrap_or("<record>"), fields)?,
+            ExprKind::Construct { callee, fields } => self.construct(callee, fields)?,
+            ExprKind::Match { scrutinee, arms } => self.match_expr(scrutinee, arms, tail)?,
+            ExprKind::While { label, cond, body } => self.while_expr(label.as_ref(), cond, body)?,
+            ExprKind::For {
+                label,
+                names,
+                iterable,
+                end,
+                body,
+            } => self.for_expr(label.as_ref(), names, iterable, end.as_deref(), body)?,
+            ExprKind::Break(label) => self.jump(label.as_ref(), true)?,
+            ExprKind::Continue(label) => self.jump(label.as_ref(), false)?,
+            ExprKind::Field { target, field } => {
+                self.expr(target)?;
+                match self.known(target) {
+                    Some(Known::Record(shape)) => {
+                        let slot = self.slot(&shape, field)?;
+                        self.emit(Expr::GetField { slot });
+                    }
+                    _ => {
+                        let name = self.string(&field.name);
+                        self.emit(Expr::GetNamedField { name });
+                    }
+                }
+            }
+            ExprKind::Update { target, fields } => {
+                self.expr(target)?;
+                self.emit(Expr::CopyRecord);
+                let shape = match self.known(target) {
+                    Some(Known::Record(shape)) => Some(shape),
+                    _ => None,
+                };
+                self.set_fields(shape.as_ref(), fields)?;
+            }
+            ExprKind::Call { callee, args } => {
+                for arg in args {
+                    self.expr(arg)?;
+                }
+                self.expr(callee)?;
+                let num_args = args.len() as u32;
+                self.emit(match tail {
+                    true => Expr::TailCall { num_args },
+                    false => Expr::Call { num_args },
+                });
+            }
+            ExprKind::Block(stmts) => self.block(stmts, false, tail)?,
+            ExprKind::Binary {
+                op: op @ (BinaryOp::And | BinaryOp::Or),
+                lhs,
+                rhs,
+            } => self.logical(*op, lhs, rhs)?,
+            ExprKind::Binary { op, lhs, rhs } => {
+                self.expr(lhs)?;
+                self.expr(rhs)?;
+                self.emit(match op {
+                    BinaryOp::Add => Expr::Add,
+                    BinaryOp::Sub => Expr::Sub,
+                    BinaryOp::Mul => Expr::Mul,
+                    BinaryOp::Div => Expr::Div,
+                    BinaryOp::Rem => Expr::Rem,
+                    BinaryOp::Pow => Expr::Pow,
+                    BinaryOp::Eq => Expr::Eq,
+                    BinaryOp::Ne => Expr::Ne,
+                    BinaryOp::Lt => Expr::Lt,
+                    BinaryOp::Le => Expr::Le,
+                    BinaryOp::Gt => Expr::Gt,
+                    BinaryOp::Ge => Expr::Ge,
+                    BinaryOp::And | BinaryOp::Or => unreachable!(),
+                });
+            }
+            ExprKind::Unary { op, operand } => {
+                self.expr(operand)?;
+                self.emit(match op {
+                    UnaryOp::Neg => Expr::Neg,
+                    UnaryOp::Not => Expr::Not,
+                });
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{parser::parse, token::Tokens, value::Value, vm::VM};
+
+    // Runs a script, returning the VM so its result can be inspected.
+    fn exec(source: &str) -> Result<(VM, Value), Diagnostic> {
+        let tokens = Tokens::from_source(source).map_err(|mut errors| errors.remove(0))?;
+        let program = compile(&parse(&tokens)?)?;
+        let mut vm = VM::new();
+        let result = vm
+            .exec(&program.exprs, &program.strings, program.entry)
+            .map_err(|err| program.diagnostic(&SourceMap::new(source), &err))?;
+        Ok((vm, result))
+    }
+
+    fn run(source: &str) -> Result<i64, Diagnostic> {
+        exec(source).map(|(_, result)| result.integer().unwrap())
+    }
+
+    fn show(source: &str) -> Result<String, Diagnostic> {
+        exec(source).map(|(vm, result)| vm.to_string(&result))
+    }
+
+    #[test]
+    fn test_script() {
+        assert_eq!(run("x = () => {\n    1 + 2\n}\n3 + 3\n"), Ok(6));
+        assert_eq!(run("x = () => {\n    1 + 2\n}\nx() + 3\n"), Ok(6));
+    }
+
+    #[test]
+    fn test_bindings() {
+        let source = "
+            add = (a, b) => {
+                c = a + b
+                c + c
+            }
+            x = 1
+            x = add(x, 2) + x
+            x
+        ";
+        assert_eq!(run(source), Ok(7));
+        assert_eq!(run("f = (g) => g(1)\nf((n) => n + 1)"), Ok(2));
+        // Expressions in a block can continue after a trailing operator
+        assert_eq!(
+            run("f = () => {\n  x = 1 +\n    2\n  x *\n  x\n}\nf()"),
+            Ok(9)
+        );
+    }
+
+    #[test]
+    fn test_scopes() {
+        // Block bindings are popped from beneath the block's value
+        assert_eq!(run("1 + { let x = 2\nx * 10 } + { let y = 3\ny }"), Ok(24));
+        assert_eq!(run("x = 1\n{ let x = 2 }\nx"), Ok(1));
+        // Shadowing, with the new binding's value computed from the old one
+        let source = "
+            let x = 1
+            let y = {
+                let x = x + 10
+                let x = x * 2
+                x
+            }
+            x + y
+        ";
+        assert_eq!(run(source), Ok(23));
+        // Assigning without `let` updates the innermost binding in scope
+        let source = "
+            x = 1
+            if x == 1 {
+                x = x + 1
+                y = 5
+                x = x + y
+            }
+            x
+        ";
+        assert_eq!(run(source), Ok(7));
+        assert_eq!(run("f = (n) => {\n n = n * 2\n n + 1\n}\nf(4)"), Ok(9));
+        // Bindings inside a match arm or beside temporaries
+        let source = "
+            Shape = Circle(r) | Square(s)
+            area = (shape) => match shape {
+                Circle(r) => { let d = r * 2\nd * d }
+                Square(s) => [1, { let t = s\nt * t }][1]
+            }
+            area(Circle(1)) + area(Square(3))
+        ";
+        assert_eq!(run(source), Ok(13));
+        assert_eq!(
+            run("{ let x = 1 }\nx"),
+            Err(Diagnostic::error(
+                "undefined name 'x'",
+                SourceLocation(14..15)
+            ))
+        );
+    }
+
+    #[test]
+    fn test_closures() {
+        assert_eq!(
+            run("adder = (x) => {\n    (y) => {\n        x + y\n    }\n}\nadder(2)(1)"),
+            Ok(3)
+        );
+        assert_eq!(run("f = (a) => (b) => (c) => a + b + c\nf(1)(2)(3)"), Ok(6));
+        // A new binding of a captured name doesn't affect the closure
+        assert_eq!(run("x = 1\nf = () => x\nlet x = 2\nf() + x"), Ok(3));
+    }
+
+    #[test]
+    fn test_loops() {
+        let source = "
+            i = 0
+            total = 0
+            while i < 10 {
+                i = i + 1
+                if i % 2 == 0 { continue }
+                if i > 7 { break }
+                total = total + i
+            }
+            total
+        ";
+        assert_eq!(run(source), Ok(1 + 3 + 5 + 7));
+        let source = "
+            total = 0
+            for x in [1, 2, 3] { total = total * 10 + x }
+            for i in 1..4 { total = total * 10 + i }
+            for i, x in [5, 6] { total = total * 10 + i + x }
+            total
+        ";
+        assert_eq!(run(source), Ok(12_312_357));
+        // Maps are iterated in insertion order, by key or by key and value
+        let source = "
+            m = {\"b\": 2, \"a\": 1}
+            s = \"\"
+            for k in m { s = s + k }
+            for k, v in m { s = \"{s} {k}={v}\" }
+            s
+        ";
+        assert_eq!(show(source).as_deref(), Ok("ba b=2 a=1"));
+        // Labels, and jumps out of nested blocks and expressions
+        let source = "
+            found = []
+            'rows: for y in 0..4 {
+                let row = y * 10
+                for x in 0..4 {
+                    if x > y { continue 'rows }
+                    if y == 3 { break 'rows }
+                    push(found, 1 + { let cell = row + x\nif cell == 11 { continue }\ncell })
+                }
+            }
+            found
+        ";
+        assert_eq!(show(source).as_deref(), Ok("[1, 11, 21, 22, 23]"));
+        // Each iteration binds a new variable, so closures see their own
+        let source = "
+            fs = []
+            for i in 0..3 { push(fs, () => { i = i * 10\ni }) }
+            fs[0]() + fs[1]() + fs[2]() + fs[2]()
+        ";
+        assert_eq!(run(source), Ok(10 + 20 + 200));
+        assert_eq!(show("while false {}").as_deref(), Ok("Nil"));
+        assert_eq!(
+            run("for x in 5 {}"),
+            Err(Diagnostic::error(
+                "expected list or map but found integer",
+                SourceLocation(0..13)
+            )
+            .with_note("in <main> at 1:1"))
+        );
+        assert_eq!(
+            run("f = () => break\nwhile true { f() }"),
+            Err(Diagnostic::error(
+                "break outside of a loop",
+                SourceLocation(10..15)
+            ))
+        );
+        assert_eq!(
+            run("while true { continue 'outer }"),
+            Err(Diagnostic::error(
+                "no enclosing loop labelled 'outer'",
+                SourceLocation(22..28)
+            ))
+        );
+        assert_eq!(
+            run("for i, j in 0..3 {}"),
+            Err(Diagnostic::error(
+                "a loop over a range binds a single name",
+                SourceLocation(7..8)
+            ))
+        );
+    }
+
+    #[test]
+    fn test_recursion() {
+        let source = "
+            fib = (n) => if n < 2 { n } else { fib(n - 2) + fib(n - 1) }
+            fib(10)
+        ";
+        assert_eq!(run(source), Ok(55));
+        // A parameter shadows the function's own name
+        assert_eq!(run("f = (f) => f + 1\nf(1)"), Ok(2));
+        // Functions bound one after the other can call each other
+        let source = "
+            is_even = (n) => if n == 0 { true } else { is_odd(n - 1) }
+            is_odd = (n) => if n == 0 { false } else { is_even(n - 1) }
+            [is_even(10), is_odd(7), is_even(3)]
+        ";
+        assert_eq!(show(source).as_deref(), Ok("[true, true, false]"));
+        let source = "
+            count = (n) => {
+                let ping = (n, k) => if n == 0 { k } else { pong(n - 1, k + 1) }
+                let pong = (n, k) => if n == 0 { k } else { ping(n - 1, k + 10) }
+                ping(n, 0)
+            }
+            count(5)
+        ";
+        assert_eq!(run(source), Ok(23));
+        // Nested lambdas and later assignments see the same functions
+        let source = "
+            a = () => b() + 1
+            b = () => c()
+            c = () => 1
+            adder = (x) => () => a() + x
+            c = () => 10
+            adder(100)()
+        ";
+        assert_eq!(run(source), Ok(111));
+        // A function can't be called before the functions it calls are bound
+        let source = "
+            f = () => g()
+            x = f()
+            g = () => 1
+        ";
+        assert_eq!(
+            run(source),
+            Err(Diagnostic::error(
+                "global 'g' used before it was defined",
+                SourceLocation(23..24)
+            )
+            .with_note("in f at 2:23")
+            .with_note("in <main> at 3:17"))
+        );
+    }
+
+    #[test]
+    fn test_upvalues() {
+        // Assignments to a captured binding are seen by the closure, and
+        // assignments made by the closure are seen outside it
+        assert_eq!(run("x = 1\nf = () => x\nx = 2\nf() + x"), Ok(4));
+        let source = "
+            counter = () => {
+                n = 0
+                (by) => {
+                    n = n + by
+                    n
+                }
+            }
+            a = counter()
+            b = counter()
+            a(1)
+            a(10)
+            b(100)
+            a(0) * 1000 + b(0)
+        ";
+        assert_eq!(run(source), Ok(11100));
+        // Closures created together share the binding, including parameters
+        // and nested captures
+        let source = "
+            make = (n) => {
+                get = () => n
+                add = (k) => { inc = () => { n = n + k }\ninc() }
+                [get, add]
+            }
+            fs = make(5)
+            fs[1](3)
+            fs[1](4)
+            fs[0]()
+        ";
+        assert_eq!(run(source), Ok(12));
+        // Pattern bindings and block bindings get their own upvalue each time
+        let source = "
+            Option = Some(v) | None
+            fs = []
+            add = (x) => match x {
+                Some(v) => push(fs, () => { v = v + 1 })
+                None => {
+                    let w = 0
+                    push(fs, () => { w = w - 1 })
+                }
+            }
+            add(Some(1))
+            add(Some(10))
+            add(None)
+            fs[0]() + fs[1]() + fs[0]() + fs[2]()
+        ";
+        assert_eq!(run(source), Ok(2 + 11 + 3 - 1));
+    }
+
+    #[test]
+    fn test_globals() {
+        // Functions can call each other wherever they're defined, and see
+        // later assignments to the globals they use
+        let source = "
+            is_even = (n) => if n == 0 { true } else { is_odd(n - 1) }
+            limit = 5
+            is_odd = (n) => if n == 0 { false } else { is_even(n - 1) }
+            limit = limit + 2
+            is_odd(limit)
+        ";
+        assert_eq!(show(source).as_deref(), Ok("true"));
+        // Functions assign to globals defined before them. A global defined
+        // after a function doesn't exist yet to the function's assignments,
+        // which bind locals instead.
+        let source = "
+            count = 0
+            total = 0
+            bump = () => { count = count + 1\ntotal = total + count }
+            bump()
+            bump()
+            total * 10 + count
+        ";
+        assert_eq!(run(source), Ok(32));
+        assert_eq!(run("f = (n) => { i = 0\n i + n }\ni = 5\nf(1) + i"), Ok(6));
+        // A global's value is kept through compaction
+        let source = "xs = [1, 2]\nys = [{ let zs = [3]\nxs }, xs]\nys";
+        assert_eq!(show(source).as_deref(), Ok("[[1, 2], [1, 2]]"));
+        // Variants and record types are known through globals
+        let source = "
+            Option = Some(v) | None
+            get = (o) => match o {
+                Some(v) => v
+                None => 0
+            }
+            get(Some(4)) + get(None)
+        ";
+        assert_eq!(run(source), Ok(4));
+        let source = "Point = record { x, y }\nf = () => Point(x: 1)";
+        assert_eq!(
+            run(source),
+            Err(Diagnostic::error(
+                "missing field 'y'",
+                SourceLocation(34..45)
+            ))
+        );
+        // Names nothing at the top level defines are still reported up front
+        assert_eq!(
+            run("f = () => h()\nf()"),
+            Err(Diagnostic::error(
+                "undefined name 'h'",
+                SourceLocation(10..11)
+            ))
+        );
+    }
+
+    #[test]
+    fn test_operators() {
+        assert_eq!(run("7 - 2 * 3 ** 2 / 4 % 3"), Ok(6));
+        assert_eq!(run("-2 ** 2 + -(-3)"), Ok(-1));
+        assert_eq!(
+            show("1 < 2 && 2 <= 2 && 4 >= 3 && 5 == 5.0").as_deref(),
+            Ok("true")
+        );
+        assert_eq!(show("3 > 4 || 5 != 5 || 1 == true").as_deref(), Ok("false"));
+        assert_eq!(show("!false && !(true && false)").as_deref(), Ok("true"));
+        // The right hand side is only evaluated if it's needed
+        assert_eq!(show("false && 1 / 0").as_deref(), Ok("false"));
+        assert_eq!(show("true || 1 / 0").as_deref(), Ok("true"));
+    }
+
+    #[test]
+    fn test_if() {
+        let source = "
+fib = (n, fib) => {
+    if n == 0 {
+        0
+    } else if n == 1 {
+        1
+    } else {
+        fib(n - 2, fib) + fib(n - 1, fib)
+    }
+}
+fib(8, fib)
+";
+        assert_eq!(run(source), Ok(21));
+        assert_eq!(show("if false { 1 }").as_deref(), Ok("Nil"));
+        assert_eq!(
+            run("x = 1\nif x { 1 } else { 2 }"),
+            Err(
+                Diagnostic::error("expected bool but found integer", SourceLocation(9..10))
+                    .with_note("in <main> at 2:4")
+            )
+        );
+        assert_eq!(
+            run("!1"),
+            Err(
+                Diagnostic::error("expected bool but found integer", SourceLocation(0..2))
+                    .with_note("in <main> at 1:1")
+            )
+        );
+    }
+
+    #[test]
+    fn test_arithmetic_errors() {
+        assert_eq!(
+            run("x = 0\n1 / x"),
+            Err(Diagnostic::error("division by zero", SourceLocation(6..11))
+                .with_note("in <main> at 2:1"))
+        );
+        assert_eq!(
+            run("2 ** 62 * 2"),
+            Err(Diagnostic::error("integer overflow", SourceLocation(0..11))
+                .with_note("in <main> at 1:1"))
+        );
+        assert_eq!(
+            run("1 + () => 1"),
+            Err(
+                Diagnostic::error("expected number but found function", SourceLocation(0..11))
+                    .with_note("in <main> at 1:1")
+            )
+        );
+    }
+
+    #[test]
+    fn test_strings() {
+        assert_eq!(
+            show(r#""foo" + "bär" + "\u{21}""#).as_deref(),
+            Ok("foobär!")
+        );
+        assert_eq!(
+            show("s = \"héllo\"\nlen(s) * 10 + find(s, \"l\")").as_deref(),
+            Ok("52")
+        );
+        assert_eq!(show(r#"slice("héllo", 1, 4)"#).as_deref(), Ok("éll"));
+        assert_eq!(
+            show(r#"upper("straße") + lower("ABC")"#).as_deref(),
+            Ok("STRASSEabc")
+        );
+        assert_eq!(show(r#"find("abc", "d")"#).as_deref(), Ok("Nil"));
+        assert_eq!(
+            show(r#"contains("abc", "bc") && "a" + "b" == "ab" && "a" != "b""#).as_deref(),
+            Ok("true")
+        );
+        // Bindings shadow builtins
+        assert_eq!(run("len = (x) => 1\nlen(\"abc\")"), Ok(1));
+        assert_eq!(
+            run(r#"f = (s) => slice(s, 2, 1)
+f("abc")"#),
+            Err(Diagnostic::error(
+                "slice 2..1 out of range for length 3",
+                SourceLocation(11..25)
+            )
+            .with_note("in f at 1:12")
+            .with_note("in <main> at 2:1"))
+        );
+        assert_eq!(
+            run(r#""a" + 1"#),
+            Err(
+                Diagnostic::error("expected number but found string", SourceLocation(0..7))
+                    .with_note("in <main> at 1:1")
+            )
+        );
+    }
+
+    #[test]
+    fn test_templates() {
+        let source =
+            "a = 1\nb = 1.5\n\"{a} + {b} = {a + b}, {upper(\"{true}\")}, {if false { 1 }}\\{}\"";
+        assert_eq!(show(source).as_deref(), Ok("1 + 1.5 = 2.5, TRUE, Nil{}"));
+    }
+
+    #[test]
+    fn test_lists() {
+        let source = "
+xs = [1, \"two\", [3]]
+push(xs, xs)
+xs[2][0] = xs[0] + 10
+pop(xs)
+push(xs, 4.5)
+ys = slice(xs, 1, 4)
+ys[0] = len(xs)
+\"{xs} {ys} {split(\"a,b,,c\", \",\")}\"
+";
+        assert_eq!(
+            show(source).as_deref(),
+            Ok(r#"[1, "two", [11], 4.5] [4, [11], 4.5] ["a", "b", "", "c"]"#)
+        );
+        // Compacting the pool after the script finishes keeps what the
+        // result refers to
+        assert_eq!(
+            show("_a = \"garbage\"\n[\"a\", [upper(\"b\")]]").as_deref(),
+            Ok(r#"["a", ["B"]]"#)
+        );
+        // A list containing itself
+        assert_eq!(show("xs = [1]\nxs[0] = xs\nxs").as_deref(), Ok("[[...]]"));
+        assert_eq!(
+            run("xs = [1, 2]\nxs[2]"),
+            Err(
+                Diagnostic::error("index 2 out of range for length 2", SourceLocation(12..17))
+                    .with_note("in <main> at 2:1")
+            )
+        );
+        assert_eq!(
+            run("xs = []\npop(xs)"),
+            Err(
+                Diagnostic::error("pop from an empty list", SourceLocation(8..15))
+                    .with_note("in <main> at 2:1")
+            )
+        );
+    }
+
+    #[test]
+    fn test_maps() {
+        let source = r#"
+m = {"b": 1, 2: [true]}
+m["a"] = 3
+m["b"] = 4
+m[false] = remove(m, 2)
+"{m} {len(m)} {keys(m)} {values(m)} {has(m, "a")} {has(m, 2)}"
+"#;
+        assert_eq!(
+            show(source).as_deref(),
+            Ok(r#"{"b": 4, "a": 3, false: [true]} 3 ["b", "a", false] [4, 3, [true]] true false"#)
+        );
+        // Compacting traces both keys and values
+        assert_eq!(
+            show("_a = \"garbage\"\n{upper(\"k\"): [lower(\"V\")]}").as_deref(),
+            Ok(r#"{"K": ["v"]}"#)
+        );
+        // A map containing itself
+        assert_eq!(show("m = {:}\nm[1] = m\nm").as_deref(), Ok("{1: {...}}"));
+        assert_eq!(
+            run("m = {\"a\": 1}\nm[\"b\"]"),
+            Err(
+                Diagnostic::error("key \"b\" not found", SourceLocation(13..19))
+                    .with_note("in <main> at 2:1")
+            )
+        );
+        assert_eq!(
+            run("{[1]: 2}"),
+            Err(
+                Diagnostic::error("list can't be used as a map key", SourceLocation(0..8))
+                    .with_note("in <main> at 1:1")
+            )
+        );
+    }
+
+    #[test]
+    fn test_records() {
+        let source = r#"
+Point = record { x, y }
+p = Point(y: 2, x: 1)
+q = p with { y: p.x + 10 }
+norm = (r) => r.x * r.x + r.y * r.y
+shift = (r, dx) => r with { x: r.x + dx }
+"{p} {q} {norm(q)} {shift(p, 5)} {Point(3, 4)} {Point} {p == p} {p == Point(x: 1, y: 2)}"
+"#;
+        assert_eq!(
+            show(source).as_deref(),
+            Ok(
+                "Point(x: 1, y: 2) Point(x: 1, y: 11) 122 Point(x: 6, y: 2) \
+                Point(x: 3, y: 4) record Point true false"
+            )
+        );
+        assert_eq!(
+            run("P = record { x }\nP(x: 1, y: 2)"),
+            Err(Diagnostic::error(
+                "record has no field 'y'",
+                SourceLocation(25..26)
+            ))
+        );
+        assert_eq!(
+            run("P = record { x, y }\nP(x: 1)"),
+            Err(Diagnostic::error(
+                "missing field 'y'",
+                SourceLocation(20..27)
+            ))
+        );
+        assert_eq!(
+            run("record { x, x }"),
+            Err(Diagnostic::error(
+                "duplicate field 'x'",
+                SourceLocation(12..13)
+            ))
+        );
+        // Fields of a record whose shape isn't known are looked up by name
+        assert_eq!(
+            run("P = record { x }\nf = (r) => r.z\nf(P(1))"),
+            Err(
+                Diagnostic::error("record P has no field 'z'", SourceLocation(28..31))
+                    .with_note("in f at 2:12")
+                    .with_note("in <main> at 3:1")
+            )
+        );
+        assert_eq!(
+            run("P = record { x }\nP(1, 2)"),
+            Err(Diagnostic::error(
+                "record P constructed with 2 fields but expected 1",
+                SourceLocation(17..24)
+            )
+            .with_note("in <main> at 2:1"))
+        );
+    }
+
+    #[test]
+    fn test_match() {
+        let source = r#"
+Option = Some(x) | None
+Shape = Circle(r) | Rect(w, h)
+describe = (v) => match v {
+    Some(0) => "zero"
+    Some(n) if n < 0 => "negative {n}"
+    Some(n) => "some {n}"
+    None => "none"
+}
+area = (s) => match s {
+    Circle(r) => 3 * r * r
+    Rect(w, h) => w * h
+}
+items = (xs) => match xs {
+    [] => "empty"
+    [x] => "one {x}"
+    [x, y, ..rest] => "{x}, {y} and {len(rest)} more"
+}
+a = "{describe(Some(0))} {describe(Some(-2))} {describe(Some(5))} {describe(None)}"
+b = "{area(Circle(2))} {area(Rect(w: 2, h: 3))} {items([])} {items([1])} {items([1, 2, 3, 4])}"
+c = match [Some("b"), true] {
+    [Some("a"), _] => 1
+    [Some("b"), false] => 2
+    [Some(_), true] => 3
+    _ => 4
+}
+"{a} {b} {c} {Some(1)} {None} {Some(1).x}"
+"#;
+        assert_eq!(
+            show(source).as_deref(),
+            Ok("zero negative -2 some 5 none 12 6 empty one 1 1, 2 and 2 more 3 Some(x: 1) None 1")
+        );
+        let source = "Option = Some(x) | None
+match Some(1) { None => 0 }";
+        assert_eq!(
+            run(source),
+            Err(
+                Diagnostic::error("no match arm matches Some(x: 1)", SourceLocation(24..51))
+                    .with_note("in <main> at 2:1")
+            )
+        );
+        assert_eq!(
+            run("Option = Some(x) | None
+match None { Some(x, y) => x }"),
+            Err(Diagnostic::error(
+                "variant 'Some' has 1 fields but the pattern has 2",
+                SourceLocation(37..47)
+            ))
+        );
+        assert_eq!(
+            run("match 1 { Some(x) => x }"),
+            Err(Diagnostic::error(
+                "unknown variant 'Some'",
+                SourceLocation(10..14)
+            ))
+        );
+        assert_eq!(
+            run("match [1, 2] { [x, x] => x }"),
+            Err(Diagnostic::error(
+                "duplicate binding 'x'",
+                SourceLocation(19..20)
+            ))
+        );
+    }
+
+    #[test]
+    fn test_match_warnings() {
+        let source = "
+Option = Some(x) | None
+_a = match None { Some(_) => 1, None => 2 }
+_b = match true { true => 1, false => 0 }
+_c = match [] { [] => 0, [_, ..] => 1 }
+_d = match None { Some(_) => 1 }
+_e = match 1 { n => n, 2 => 2 }
+_f = match 1 { n if n > 0 => n }
+";
+        let tokens = Tokens::from_source(source).unwrap();
+        let program = compile(&parse(&tokens).unwrap()).unwrap();
+        let non_exhaustive = |location| {
+            Diagnostic::warning("match doesn't cover every value", SourceLocation(location))
+                .with_note("add a '_' arm to match anything else")
+        };
+        assert_eq!(
+            program.warnings,
+            [
+                non_exhaustive(156..183),
+                Diagnostic::warning("unreachable match arm", SourceLocation(207..208)),
+                non_exhaustive(221..248),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_floats() {
+        assert_eq!(show("1.5 + 1").as_deref(), Ok("2.5"));
+        assert_eq!(show("7 / 2").as_deref(), Ok("3"));
+        assert_eq!(show("7 / 2.0").as_deref(), Ok("3.5"));
+        assert_eq!(show("2 ** -1").as_deref(), Ok("0.5"));
+        assert_eq!(show("-1.5e20 * 2").as_deref(), Ok("-3e20"));
+        assert_eq!(show("0.1 + 0.2").as_deref(), Ok("0.30000000000000004"));
+        assert_eq!(show("1 / 0.0").as_deref(), Ok("inf"));
+        assert_eq!(
+            show("1 == 1.0 && 2.5 < 3 && !(1e0 >= 2)").as_deref(),
+            Ok("true")
+        );
+    }
+
+    #[test]
+    fn test_backtrace() {
+        let source = "
+inner = (f) => f() + 1
+outer = (f) => {
+    inner(f) + 1
+}
+outer((x) => x)
+";
+        assert_eq!(
+            run(source),
+            Err(Diagnostic::error(
+                "function called with 0 args but expected 1",
+                SourceLocation(16..19)
+            )
+            .with_note("in inner at 2:16")
+            .with_note("in outer at 4:5")
+            .with_note("in <main> at 6:1"))
+        );
+    }
+
+    #[test]
+    fn test_tail_calls() {
+        // Tail calls reuse the caller's frame, so deep recursion doesn't grow
+        // the stack of frames
+        let source = "
+            count = (n, total) => if n == 0 { total } else { count(n - 1, total + n) }
+            even = (n) => match n {
+                0 => true
+                _ => { let m = n - 1\nodd(m) }
+            }
+            odd = (n) => if n == 0 { false } else { even(n - 1) }
+            both = (n) => n != 0 && odd(n)
+            [count(10000, 0), even(10001), both(1)]
+        ";
+        assert_eq!(show(source).as_deref(), Ok("[50005000, false, true]"));
+        // Only the calls in tail position reuse the frame, and not the right
+        // hand side of `&&`, whose result is checked
+        let tokens = Tokens::from_source(source).unwrap();
+        let program = compile(&parse(&tokens).unwrap()).unwrap();
+        let tail_calls = program
+            .exprs
+            .iter()
+            .filter(|expr| matches!(expr, Expr::TailCall { .. }))
+            .count();
+        assert_eq!(tail_calls, 3);
+        let source = "
+f = (n) => if n == 0 { 1 / 0 } else { f(n - 1) }
+g = (n) => f(n) + 1
+g(3)
+";
+        assert_eq!(
+            run(source),
+            Err(
+                Diagnostic::error("division by zero", SourceLocation(24..29))
+                    .with_note("in f at 2:24")
+                    .with_note("in g at 3:12")
+                    .with_note("in <main> at 4:1")
+            )
+        );
+    }
+
+    #[test]
+    fn test_errors() {
+        assert_eq!(
+            run("x = 1\ny + 1"),
+            Err(Diagnostic::error(
+                "undefined name 'y'",
+                SourceLocation(6..7)
+            ))
+        );
+        assert_eq!(
+            run("f = (a, a) => a"),
+            Err(Diagnostic::error(
+                "duplicate parameter 'a'",
+                SourceLocation(8..9)
+            ))
+        );
+    }
+}
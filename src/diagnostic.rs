@@ -0,0 +1,225 @@
+use crate::{source_map::SourceMap, token::SourceLocation};
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Severity {
+    Error,
+    Warning,
+}
+
+/// A message about a problem in a script, reported by any stage from the lexer
+/// through to the VM.
+#[derive(Debug, PartialEq)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    pub message: String,
+    pub location: Option<SourceLocation>,
+    pub notes: Vec<String>,
+}
+
+const RESET: &str = "\x1b[0m";
+const BOLD: &str = "\x1b[1m";
+const RED: &str = "\x1b[1;31m";
+const YELLOW: &str = "\x1b[1;33m";
+const BLUE: &str = "\x1b[1;34m";
+
+impl Diagnostic {
+    pub fn error(message: impl Into<String>, location: SourceLocation) -> Self {
+        Self {
+            severity: Severity::Error,
+            message: message.into(),
+            location: Some(location),
+            notes: Vec::new(),
+        }
+    }
+
+    pub fn warning(message: impl Into<String>, location: SourceLocation) -> Self {
+        Self {
+            severity: Severity::Warning,
+            ..Self::error(message, location)
+        }
+    }
+
+    pub fn with_note(mut self, note: impl Into<String>) -> Self {
+        self.notes.push(note.into());
+        self
+    }
+
+    /// Renders the diagnostic on a single line prefixed with its position, as
+    /// editors parsing compiler output expect. Columns are counted in UTF-16
+    /// code units.
+    pub fn render_short(&self, path: &str, map: &SourceMap) -> String {
+        let severity = match self.severity {
+            Severity::Error => "error",
+            Severity::Warning => "warning",
+        };
+        match &self.location {
+            Some(location) => {
+                let position = map.utf16_position(location.0.start);
+                format!("{path}:{position}: {severity}: {}", self.message)
+            }
+            None => format!("{path}: {severity}: {}", self.message),
+        }
+    }
+
+    /// Renders the diagnostic with the source line it refers to, underlining
+    /// the offending span. Uses ANSI escape codes if `color` is set.
+    pub fn render(&self, map: &SourceMap, color: bool) -> String {
+        let paint = |code: &str, text: &str| {
+            if color {
+                format!("{code}{text}{RESET}")
+            } else {
+                text.to_string()
+            }
+        };
+        let (severity, highlight) = match self.severity {
+            Severity::Error => ("error", RED),
+            Severity::Warning => ("warning", YELLOW),
+        };
+        let mut lines = vec![format!(
+            "{}{}",
+            paint(highlight, severity),
+            paint(BOLD, &format!(": {}", self.message))
+        )];
+        let mut gutter = String::new();
+        if let Some(location) = &self.location {
+            let start = map.position(location.0.start);
+            let end = map.position(location.0.end);
+            let line = map.line(start.line);
+            // Only the first line of a multi-line span is underlined
+            let width = if end.line == start.line {
+                end.column - start.column
+            } else {
+                line.chars().count() + 1 - start.column
+            };
+            let line_no = start.line.to_string();
+            gutter = " ".repeat(line_no.len());
+            lines.push(format!("{gutter}{} {start}", paint(BLUE, "-->")));
+            lines.push(paint(BLUE, &format!("{gutter} |")));
+            lines.push(format!("{} {line}", paint(BLUE, &format!("{line_no} |"))));
+            // Tabs are copied so the underline lines up however they're shown
+            let padding: String = line
+                .chars()
+                .map(|c| if c == '\t' { '\t' } else { ' ' })
+                .chain(std::iter::repeat(' '))
+                .take(start.column - 1)
+                .collect();
+            lines.push(format!(
+                "{} {padding}{}",
+                paint(BLUE, &format!("{gutter} |")),
+                paint(highlight, &"^".repeat(width.max(1)))
+            ));
+        }
+        for note in &self.notes {
+            lines.push(format!("{gutter} {} {note}", paint(BLUE, "= note:")));
+        }
+        lines.join("\n")
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_render() {
+        let source = "x = 1\ny = x + z\n";
+        let diagnostic = Diagnostic::error("undefined name 'z'", SourceLocation(14..15))
+            .with_note("names must be bound before they're used");
+        assert_eq!(
+            diagnostic.render(&SourceMap::new(source), false),
+            [
+                "error: undefined name 'z'",
+                " --> 2:9",
+                "  |",
+                "2 | y = x + z",
+                "  |         ^",
+                "  = note: names must be bound before they're used",
+            ]
+            .join("\n")
+        );
+        let diagnostic = Diagnostic::warning("unreachable match arm", SourceLocation(6..15));
+        assert_eq!(
+            diagnostic.render(&SourceMap::new(source), false),
+            [
+                "warning: unreachable match arm",
+                " --> 2:1",
+                "  |",
+                "2 | y = x + z",
+                "  | ^^^^^^^^^",
+            ]
+            .join("\n")
+        );
+    }
+
+    #[test]
+    fn test_render_multi_byte() {
+        let source = "s = \"héllo\" + ☃\n";
+        let diagnostic = Diagnostic::error("unexpected token '☃'", SourceLocation(15..18));
+        assert_eq!(
+            diagnostic.render(&SourceMap::new(source), false),
+            [
+                "error: unexpected token '☃'",
+                " --> 1:15",
+                "  |",
+                "1 | s = \"héllo\" + ☃",
+                "  |               ^",
+            ]
+            .join("\n")
+        );
+    }
+
+    #[test]
+    fn test_render_tabs() {
+        let source = "x = 1\n\ty = x +\tz\n";
+        let diagnostic = Diagnostic::error("undefined name 'z'", SourceLocation(15..16));
+        assert_eq!(
+            diagnostic.render(&SourceMap::new(source), false),
+            [
+                "error: undefined name 'z'",
+                " --> 2:10",
+                "  |",
+                "2 | \ty = x +\tz",
+                "  | \t       \t^",
+            ]
+            .join("\n")
+        );
+    }
+
+    #[test]
+    fn test_render_short() {
+        let source = "s = \"𝄞\" + ☃\n";
+        let diagnostic = Diagnostic::error("unexpected token '☃'", SourceLocation(13..16));
+        assert_eq!(
+            diagnostic.render_short("script.txt", &SourceMap::new(source)),
+            "script.txt:1:12: error: unexpected token '☃'"
+        );
+    }
+
+    #[test]
+    fn test_render_end_of_file() {
+        let diagnostic = Diagnostic::error("expected expression", SourceLocation(4..4));
+        let map = SourceMap::new("1 + ");
+        assert_eq!(
+            diagnostic.render(&map, false),
+            [
+                "error: expected expression",
+                " --> 1:5",
+                "  |",
+                "1 | 1 + ",
+                "  |     ^",
+            ]
+            .join("\n")
+        );
+        assert_eq!(
+            diagnostic.render(&map, true),
+            [
+                "\x1b[1;31merror\x1b[0m\x1b[1m: expected expression\x1b[0m",
+                " \x1b[1;34m-->\x1b[0m 1:5",
+                "\x1b[1;34m  |\x1b[0m",
+                "\x1b[1;34m1 |\x1b[0m 1 + ",
+                "\x1b[1;34m  |\x1b[0m     \x1b[1;31m^\x1b[0m",
+            ]
+            .join("\n")
+        );
+    }
+}
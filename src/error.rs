@@ -0,0 +1,132 @@
+use std::fmt::Display;
+
+#[derive(Debug, PartialEq)]
+pub enum ErrorKind {
+    TypeMismatch {
+        expected: &'static str,
+        found: &'static str,
+    },
+    Arity {
+        expected: u32,
+        found: u32,
+    },
+    Overflow,
+    DivisionByZero,
+    SliceOutOfRange {
+        start: i64,
+        end: i64,
+        len: usize,
+    },
+    IndexOutOfRange {
+        index: i64,
+        len: usize,
+    },
+    EmptyList,
+    Unhashable {
+        found: &'static str,
+    },
+    MissingKey {
+        key: String,
+    },
+    EmptySeparator,
+    FieldCount {
+        record: String,
+        expected: usize,
+        found: usize,
+    },
+    NoField {
+        record: String,
+        field: String,
+    },
+    NoMatch {
+        value: String,
+    },
+    StackUnderflow,
+    BadJump {
+        target: usize,
+    },
+    InvalidObject {
+        index: usize,
+    },
+    BadConstant {
+        index: usize,
+    },
+    BadSlot {
+        slot: usize,
+    },
+    UndefinedGlobal {
+        name: String,
+    },
+}
+
+/// A function call that was active when an error was raised.
+#[derive(Debug, PartialEq)]
+pub struct TraceFrame {
+    /// The entry address of the called function.
+    pub entry: usize,
+    /// The address of the instruction the call was executing.
+    pub addr: usize,
+}
+
+/// An error raised while executing a program, along with the address of the
+/// instruction that caused it and the calls that led there, innermost first.
+#[derive(Debug, PartialEq)]
+pub struct RuntimeError {
+    pub addr: usize,
+    pub kind: ErrorKind,
+    pub backtrace: Vec<TraceFrame>,
+}
+
+impl Display for ErrorKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ErrorKind::TypeMismatch { expected, found } => {
+                write!(f, "expected {expected} but found {found}")
+            }
+            ErrorKind::Arity { expected, found } => {
+                write!(
+                    f,
+                    "function called with {found} args but expected {expected}"
+                )
+            }
+            ErrorKind::Overflow => write!(f, "integer overflow"),
+            ErrorKind::DivisionByZero => write!(f, "division by zero"),
+            ErrorKind::SliceOutOfRange { start, end, len } => {
+                write!(f, "slice {start}..{end} out of range for length {len}")
+            }
+            ErrorKind::IndexOutOfRange { index, len } => {
+                write!(f, "index {index} out of range for length {len}")
+            }
+            ErrorKind::Unhashable { found } => write!(f, "{found} can't be used as a map key"),
+            ErrorKind::MissingKey { key } => write!(f, "key {key} not found"),
+            ErrorKind::EmptyList => write!(f, "pop from an empty list"),
+            ErrorKind::EmptySeparator => write!(f, "split with an empty separator"),
+            ErrorKind::FieldCount {
+                record,
+                expected,
+                found,
+            } => write!(
+                f,
+                "record {record} constructed with {found} fields but expected {expected}"
+            ),
+            ErrorKind::NoField { record, field } => {
+                write!(f, "record {record} has no field '{field}'")
+            }
+            ErrorKind::NoMatch { value } => write!(f, "no match arm matches {value}"),
+            ErrorKind::StackUnderflow => write!(f, "stack underflow"),
+            ErrorKind::BadJump { target } => write!(f, "jump to invalid address {target}"),
+            ErrorKind::InvalidObject { index } => write!(f, "invalid object reference {index}"),
+            ErrorKind::BadConstant { index } => write!(f, "invalid constant reference {index}"),
+            ErrorKind::BadSlot { slot } => write!(f, "invalid record slot {slot}"),
+            ErrorKind::UndefinedGlobal { name } => {
+                write!(f, "global '{name}' used before it was defined")
+            }
+        }
+    }
+}
+
+impl Display for RuntimeError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} at instruction {:04}", self.kind, self.addr)
+    }
+}
@@ -1,29 +1,106 @@
-use std::{fs, path::PathBuf};
+use std::{
+    fs,
+    io::{self, IsTerminal},
+    path::PathBuf,
+    process::ExitCode,
+};
 
-use clap::Parser;
+use clap::{Parser, ValueEnum};
+use diagnostic::Diagnostic;
+use source_map::SourceMap;
 use token::Tokens;
+use vm::VM;
 
+mod ast;
+mod builtin;
+mod capture;
+mod compiler;
+mod diagnostic;
+mod error;
 mod function;
+mod map;
+mod matching;
 mod object;
+mod parser;
 mod pool;
+mod record;
+mod source_map;
 mod thread;
 mod token;
 mod value;
 mod vm;
 
+#[derive(Clone, Copy, Debug, ValueEnum)]
+enum MessageFormat {
+    /// Show the offending source line with the span underlined
+    Human,
+    /// One line per message, prefixed with the script's path and position
+    Short,
+}
+
 /// Interpreter test program
 #[derive(Parser, Debug)]
 #[command(version, about, long_about = None)]
 struct Args {
     script: PathBuf,
+    /// Print the stack and each instruction as it's executed
+    #[arg(long)]
+    debug: bool,
+    /// Print the script's tokens, including comments, instead of running it
+    #[arg(long)]
+    tokens: bool,
+    /// How to print errors and warnings
+    #[arg(long, value_enum, default_value_t = MessageFormat::Human)]
+    message_format: MessageFormat,
+}
+
+fn run(
+    map: &SourceMap,
+    source: &str,
+    debug: bool,
+    report: impl Fn(&Diagnostic),
+) -> Result<String, Vec<Diagnostic>> {
+    let tokens = Tokens::from_source(source)?;
+    let program = parser::parse(&tokens).map_err(|diagnostic| vec![diagnostic])?;
+    let program = compiler::compile(&program).map_err(|diagnostic| vec![diagnostic])?;
+    program.warnings.iter().for_each(report);
+    let mut vm = VM::new();
+    vm.debug = debug;
+    let result = vm
+        .exec(&program.exprs, &program.strings, program.entry)
+        .map_err(|err| vec![program.diagnostic(map, &err)])?;
+    Ok(vm.to_string(&result))
 }
 
-fn main() {
+fn main() -> ExitCode {
     let args = Args::parse();
-    let script = fs::read_to_string(args.script).expect("Unable to read file");
-    let tokens = match Tokens::from_source(&script) {
-        Ok(tokens) => tokens,
-        Err(msg) => panic!("{msg}"),
+    let source = match fs::read_to_string(&args.script) {
+        Ok(source) => source,
+        Err(err) => {
+            eprintln!("error: unable to read {}: {err}", args.script.display());
+            return ExitCode::FAILURE;
+        }
+    };
+    let map = SourceMap::new(&source);
+    let path = args.script.display().to_string();
+    let color = io::stderr().is_terminal();
+    let report = |diagnostic: &Diagnostic| match args.message_format {
+        MessageFormat::Human => eprintln!("{}", diagnostic.render(&map, color)),
+        MessageFormat::Short => eprintln!("{}", diagnostic.render_short(&path, &map)),
+    };
+    let result = if args.tokens {
+        Tokens::with_comments(&source).map(|tokens| tokens.to_string())
+    } else {
+        run(&map, &source, args.debug, report)
     };
-    println!("{tokens}");
+    match result {
+        Ok(result) => {
+            println!("{result}");
+            ExitCode::SUCCESS
+        }
+        Err(diagnostics) => {
+            diagnostics.iter().for_each(report);
+            ExitCode::FAILURE
+        }
+    }
 }
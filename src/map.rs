@@ -0,0 +1,60 @@
+use std::collections::HashMap;
+
+use crate::value::Value;
+
+/// The hashable form of a value used as a map key. Keys are equal exactly when
+/// the values they were made from are equal under `==`.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub enum Key {
+    Nil,
+    Bool(bool),
+    Integer(i64),
+    String(String),
+}
+
+/// A map from keys to values that remembers the order keys were first
+/// inserted in. Keys are kept as values as well, so they can be handed back to
+/// scripts and traced by `ObjectPool::compact`.
+#[derive(Default)]
+pub struct Map {
+    pub keys: Vec<Value>,
+    pub values: Vec<Value>,
+    // The position of each key in `keys` and `values`
+    index: HashMap<Key, usize>,
+}
+
+impl Map {
+    pub fn len(&self) -> usize {
+        self.keys.len()
+    }
+
+    pub fn get(&self, key: &Key) -> Option<Value> {
+        self.index.get(key).map(|&i| self.values[i])
+    }
+
+    /// Sets the value for a key. A key that's already present keeps its
+    /// position.
+    pub fn insert(&mut self, key: Key, key_value: Value, value: Value) {
+        match self.index.get(&key) {
+            Some(&i) => self.values[i] = value,
+            None => {
+                self.index.insert(key, self.keys.len());
+                self.keys.push(key_value);
+                self.values.push(value);
+            }
+        }
+    }
+
+    pub fn remove(&mut self, key: &Key) -> Option<Value> {
+        let i = self.index.remove(key)?;
+        self.keys.remove(i);
+        let value = self.values.remove(i);
+        // Entries after the removed one have moved down
+        for position in self.index.values_mut() {
+            if *position > i {
+                *position -= 1;
+            }
+        }
+        Some(value)
+    }
+}
@@ -0,0 +1,363 @@
+use crate::{compiler::Place, token::SourceLocation};
+
+/// Identifies a variant of a union declaration.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Tag {
+    /// Distinguishes unions declared in the same program.
+    pub union: usize,
+    pub index: usize,
+    /// The number of variants in the union.
+    pub count: usize,
+}
+
+/// A step from a value to one of its parts.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Access {
+    /// A field of a record, by slot.
+    Field(u32),
+    /// An item of a list.
+    Item(u32),
+    /// The items of a list from an index onwards.
+    Rest(u32),
+}
+
+/// The steps from the matched value to a part of it.
+pub type Path = Vec<Access>;
+
+/// A check of a single part of the matched value.
+#[derive(Clone, Debug)]
+pub enum Test {
+    /// Whether the value is a record of a variant, whose constructor is held
+    /// in `place`.
+    Variant {
+        tag: Tag,
+        place: Place,
+    },
+    Integer(i64),
+    Bool(bool),
+    String(String),
+    /// Whether the value is a list of exactly `len` items, or at least `len`
+    /// if `rest` is set.
+    List {
+        len: u32,
+        rest: bool,
+    },
+}
+
+impl PartialEq for Test {
+    fn eq(&self, other: &Self) -> bool {
+        match (self, other) {
+            // The same variant may be reachable through different bindings
+            (Test::Variant { tag: a, .. }, Test::Variant { tag: b, .. }) => a == b,
+            (Test::Integer(a), Test::Integer(b)) => a == b,
+            (Test::Bool(a), Test::Bool(b)) => a == b,
+            (Test::String(a), Test::String(b)) => a == b,
+            (Test::List { len: a, rest: x }, Test::List { len: b, rest: y }) => a == b && x == y,
+            _ => false,
+        }
+    }
+}
+
+impl Test {
+    // Whether this test's outcome follows from another test on the same value
+    // having had `outcome`. Only facts that hold for any value are used.
+    fn implied_by(&self, other: &Test, outcome: bool) -> Option<bool> {
+        if self == other {
+            return Some(outcome);
+        }
+        match (self, other) {
+            (
+                Test::List { len, rest },
+                Test::List {
+                    len: m,
+                    rest: m_rest,
+                },
+            ) => {
+                let (len, m) = (*len, *m);
+                match (rest, m_rest, outcome) {
+                    // Exactly `m` items
+                    (false, false, true) => Some(false),
+                    (true, false, true) => Some(m >= len),
+                    // At least `m` items
+                    (false, true, true) if len < m => Some(false),
+                    (true, true, true) if m >= len => Some(true),
+                    // Fewer than `m` items
+                    (_, true, false) if len >= m => Some(false),
+                    _ => None,
+                }
+            }
+            // A value can only pass one test that isn't about list length
+            _ if outcome => Some(false),
+            _ => None,
+        }
+    }
+}
+
+/// A pattern after its variants have been resolved.
+#[derive(Clone)]
+pub enum Pat {
+    /// Matches anything, binding it if there's a name.
+    Any(Option<(String, SourceLocation)>),
+    /// Matches if the test passes and each of the parts matches its pattern.
+    /// Variants have a part for each field and lists one for each item
+    /// followed by one for the rest if it may be longer.
+    Test(Test, Vec<Pat>),
+}
+
+/// The code to run for a match, as a tree of tests on parts of the value.
+pub enum Tree {
+    /// Nothing matched.
+    Fail,
+    /// The arm matched, with its bindings in source order. If the arm has a
+    /// guard, `fallback` decides what happens when it's false.
+    Leaf {
+        arm: usize,
+        bindings: Vec<Binding>,
+        fallback: Option<Box<Tree>>,
+    },
+    Test {
+        path: Path,
+        test: Test,
+        then: Box<Tree>,
+        otherwise: Box<Tree>,
+    },
+}
+
+#[derive(Clone)]
+pub struct Binding {
+    pub name: String,
+    pub location: SourceLocation,
+    pub path: Path,
+}
+
+pub struct Decisions {
+    pub tree: Tree,
+    /// Whether every value of the types the patterns test for is matched by
+    /// some arm, ignoring guards.
+    pub exhaustive: bool,
+    /// Whether each arm can be reached.
+    pub reachable: Vec<bool>,
+}
+
+/// Builds a decision tree for arms with the given patterns, where `guarded`
+/// says which arms have guards. Arms are tried in order, but each part of the
+/// value is tested at most once on any path through the tree.
+pub fn decide(patterns: Vec<Pat>, guarded: Vec<bool>) -> Decisions {
+    let rows = patterns
+        .into_iter()
+        .enumerate()
+        .map(|(arm, pattern)| {
+            let mut row = Row {
+                arm,
+                constraints: Vec::new(),
+                bindings: Vec::new(),
+            };
+            row.constrain(Vec::new(), pattern);
+            row
+        })
+        .collect();
+    let mut builder = Builder {
+        reachable: vec![false; guarded.len()],
+        guarded,
+        exhaustive: true,
+    };
+    let tree = builder.build(rows, &mut Vec::new());
+    Decisions {
+        tree,
+        exhaustive: builder.exhaustive,
+        reachable: builder.reachable,
+    }
+}
+
+// An arm that's still a candidate, with the tests left to pass
+#[derive(Clone)]
+struct Row {
+    arm: usize,
+    constraints: Vec<(Path, Test, Vec<Pat>)>,
+    bindings: Vec<Binding>,
+}
+
+impl Row {
+    fn constrain(&mut self, path: Path, pattern: Pat) {
+        match pattern {
+            Pat::Any(None) => {}
+            Pat::Any(Some((name, location))) => self.bindings.push(Binding {
+                name,
+                location,
+                path,
+            }),
+            Pat::Test(test, parts) => self.constraints.push((path, test, parts)),
+        }
+    }
+
+    // Updates the row for `test` on the value at `path` having had `outcome`,
+    // or returns `None` if the row can no longer match.
+    fn assume(mut self, path: &Path, test: &Test, outcome: bool) -> Option<Row> {
+        let Some(i) = self.constraints.iter().position(|(p, _, _)| p == path) else {
+            return Some(self);
+        };
+        if outcome && self.constraints[i].1 == *test {
+            let (path, test, parts) = self.constraints.remove(i);
+            for (j, part) in parts.into_iter().enumerate() {
+                let access = match test {
+                    Test::List { len, .. } if j as u32 == len => Access::Rest(len),
+                    Test::List { .. } => Access::Item(j as u32),
+                    _ => Access::Field(j as u32),
+                };
+                let mut path = path.clone();
+                path.push(access);
+                self.constrain(path, part);
+            }
+            return Some(self);
+        }
+        match self.constraints[i].1.implied_by(test, outcome) {
+            Some(false) => None,
+            _ => Some(self),
+        }
+    }
+}
+
+struct Builder {
+    guarded: Vec<bool>,
+    exhaustive: bool,
+    reachable: Vec<bool>,
+}
+
+impl Builder {
+    // `facts` holds the outcome of each test made on the way here.
+    fn build(&mut self, rows: Vec<Row>, facts: &mut Vec<(Path, Test, bool)>) -> Tree {
+        let Some(first) = rows.first() else {
+            if !covered(facts) {
+                self.exhaustive = false;
+            }
+            return Tree::Fail;
+        };
+        let Some((path, test, _)) = first.constraints.first() else {
+            let arm = first.arm;
+            self.reachable[arm] = true;
+            let fallback =
+                self.guarded[arm].then(|| Box::new(self.build(rows[1..].to_vec(), facts)));
+            let mut bindings = rows.into_iter().next().unwrap().bindings;
+            bindings.sort_by_key(|binding| binding.location.0.start);
+            return Tree::Leaf {
+                arm,
+                bindings,
+                fallback,
+            };
+        };
+        let (path, test) = (path.clone(), test.clone());
+        let known = facts
+            .iter()
+            .filter(|(p, _, _)| *p == path)
+            .find_map(|(_, fact, outcome)| test.implied_by(fact, *outcome));
+        let assume = |outcome| {
+            rows.iter()
+                .filter_map(|row| row.clone().assume(&path, &test, outcome))
+                .collect()
+        };
+        if let Some(outcome) = known {
+            return self.build(assume(outcome), facts);
+        }
+        let (then_rows, otherwise_rows) = (assume(true), assume(false));
+        facts.push((path.clone(), test.clone(), true));
+        let then = self.build(then_rows, facts);
+        facts.last_mut().unwrap().2 = false;
+        let otherwise = self.build(otherwise_rows, facts);
+        facts.pop();
+        Tree::Test {
+            path,
+            test,
+            then: Box::new(then),
+            otherwise: Box::new(otherwise),
+        }
+    }
+}
+
+// Whether the tests that failed on the way here rule out every value of the
+// type they test for, such as every variant of a union. Values of other types
+// still reach `Tree::Fail` at runtime.
+fn covered(facts: &[(Path, Test, bool)]) -> bool {
+    facts.iter().any(|(path, test, _)| {
+        let failed = |test: &Test| {
+            facts
+                .iter()
+                .any(|(p, fact, outcome)| p == path && !outcome && fact == test)
+        };
+        match test {
+            Test::Variant { tag, .. } => (0..tag.count).all(|index| {
+                failed(&Test::Variant {
+                    tag: Tag { index, ..*tag },
+                    place: Place::Local(0),
+                })
+            }),
+            Test::Bool(_) => failed(&Test::Bool(true)) && failed(&Test::Bool(false)),
+            Test::List { len, rest: true } => {
+                failed(test) && (0..*len).all(|len| failed(&Test::List { len, rest: false }))
+            }
+            _ => false,
+        }
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn variant(index: usize, parts: Vec<Pat>) -> Pat {
+        let tag = Tag {
+            union: 0,
+            index,
+            count: 2,
+        };
+        Pat::Test(
+            Test::Variant {
+                tag,
+                place: Place::Local(0),
+            },
+            parts,
+        )
+    }
+
+    // Counts the tests on the longest path through the tree.
+    fn depth(tree: &Tree) -> usize {
+        match tree {
+            Tree::Fail => 0,
+            Tree::Leaf { fallback, .. } => fallback.as_deref().map_or(0, depth),
+            Tree::Test {
+                then, otherwise, ..
+            } => 1 + depth(then).max(depth(otherwise)),
+        }
+    }
+
+    #[test]
+    fn test_decide() {
+        // Some(1), Some(_), None
+        let patterns = vec![
+            variant(0, vec![Pat::Test(Test::Integer(1), Vec::new())]),
+            variant(0, vec![Pat::Any(None)]),
+            variant(1, Vec::new()),
+        ];
+        let decisions = decide(patterns, vec![false; 3]);
+        assert!(decisions.exhaustive);
+        assert_eq!(decisions.reachable, [true, true, true]);
+        // The variant is only tested once on the way to the second arm
+        assert_eq!(depth(&decisions.tree), 2);
+
+        // [], [_, ..], [_, _]
+        let list = |len, rest, parts| Pat::Test(Test::List { len, rest }, parts);
+        let any = || Pat::Any(None);
+        let patterns = vec![
+            list(0, false, Vec::new()),
+            list(1, true, vec![any(), any()]),
+            list(2, false, vec![any(), any()]),
+        ];
+        let decisions = decide(patterns, vec![false; 3]);
+        assert!(decisions.exhaustive);
+        assert_eq!(decisions.reachable, [true, true, false]);
+
+        // A guard leaves later arms reachable
+        let decisions = decide(vec![any(), variant(1, Vec::new())], vec![true, false]);
+        assert!(!decisions.exhaustive);
+        assert_eq!(decisions.reachable, [true, true]);
+    }
+}
@@ -1,23 +1,44 @@
-use paste::paste;
-use std::{
-    collections::{HashMap, HashSet},
-    fmt::Display,
+use std::fmt::Display;
+
+use crate::{
+    function::Function,
+    map::Map,
+    record::{Record, Shape},
+    thread::Thread,
+    value::Value,
 };
 
-use crate::{function::Function, thread::Thread, value::Value};
+pub type List = Vec<Value>;
+
+/// A binding that's shared between the closures capturing it, so each of them
+/// sees the others' assignments to it.
+pub type Upvalue = Value;
 
 pub enum Object {
     Function(Function),
+    List(List),
+    Map(Map),
+    Record(Record),
+    Shape(Shape),
+    String(String),
     Thread(Thread),
+    Upvalue(Upvalue),
 }
 
 impl Object {
+    // Every object holds its values in at most two slices
     pub fn references(&self) -> impl Iterator<Item = &usize> {
-        match self {
-            Object::Function(f) => f.closure.iter(),
-            Object::Thread(t) => t.stack.iter(),
-        }
-        .filter_map(|value| {
+        let (values, more): (&[Value], &[Value]) = match self {
+            Object::Function(f) => (&f.closure, &[]),
+            Object::List(items) => (items, &[]),
+            Object::Map(map) => (&map.keys, &map.values),
+            Object::Record(record) => (&record.fields, std::slice::from_ref(&record.shape)),
+            Object::Shape(_) => (&[], &[]),
+            Object::String(_) => (&[], &[]),
+            Object::Thread(t) => (&t.stack, &[]),
+            Object::Upvalue(value) => (std::slice::from_ref(value), &[]),
+        };
+        values.iter().chain(more).filter_map(|value| {
             if let Value::Object(i) = value {
                 Some(i)
             } else {
@@ -27,11 +48,17 @@ impl Object {
     }
 
     pub fn references_mut(&mut self) -> impl Iterator<Item = &mut usize> {
-        match self {
-            Object::Function(f) => f.closure.iter_mut(),
-            Object::Thread(t) => t.stack.iter_mut(),
-        }
-        .filter_map(|value| {
+        let (values, more): (&mut [Value], &mut [Value]) = match self {
+            Object::Function(f) => (&mut f.closure, &mut []),
+            Object::List(items) => (items, &mut []),
+            Object::Map(map) => (&mut map.keys, &mut map.values),
+            Object::Record(record) => (&mut record.fields, std::slice::from_mut(&mut record.shape)),
+            Object::Shape(_) => (&mut [], &mut []),
+            Object::String(_) => (&mut [], &mut []),
+            Object::Thread(t) => (&mut t.stack, &mut []),
+            Object::Upvalue(value) => (std::slice::from_mut(value), &mut []),
+        };
+        values.iter_mut().chain(more).filter_map(|value| {
             if let Value::Object(i) = value {
                 Some(i)
             } else {
@@ -52,7 +79,13 @@ impl Display for Object {
                     function.closure.len()
                 )
             }
-            Object::Thread(_) => format!("thread"),
+            Object::List(items) => format!("list len:{}", items.len()),
+            Object::Map(map) => format!("map len:{}", map.len()),
+            Object::Record(record) => format!("record len:{}", record.fields.len()),
+            Object::Shape(shape) => format!("record {}", shape.name),
+            Object::String(string) => string.clone(),
+            Object::Thread(_) => "thread".to_string(),
+            Object::Upvalue(_) => "upvalue".to_string(),
         };
         write!(f, "{s}")
     }
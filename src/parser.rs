@@ -0,0 +1,1087 @@
+use crate::{
+    ast::{
+        Arm, BinaryOp, Expr, ExprKind, Name, Pattern, PatternKind, Program, Stmt, UnaryOp, Variant,
+    },
+    diagnostic::Diagnostic,
+    token::{Kind, SourceLocation, TemplatePart, Token, Tokens},
+};
+
+type ParseResult<T> = Result<T, Diagnostic>;
+
+#[derive(Clone, Copy, PartialEq)]
+enum Assoc {
+    Left,
+    Right,
+}
+
+// Binary operators with their precedence, where higher binds tighter, and
+// associativity.
+fn binary_op(kind: &Kind) -> Option<(BinaryOp, u8, Assoc)> {
+    let op = match kind {
+        Kind::PipePipe => (BinaryOp::Or, 1, Assoc::Left),
+        Kind::AmpAmp => (BinaryOp::And, 2, Assoc::Left),
+        Kind::EqualEqual => (BinaryOp::Eq, 3, Assoc::Left),
+        Kind::BangEqual => (BinaryOp::Ne, 3, Assoc::Left),
+        Kind::Less => (BinaryOp::Lt, 4, Assoc::Left),
+        Kind::LessEqual => (BinaryOp::Le, 4, Assoc::Left),
+        Kind::Greater => (BinaryOp::Gt, 4, Assoc::Left),
+        Kind::GreaterEqual => (BinaryOp::Ge, 4, Assoc::Left),
+        Kind::Plus => (BinaryOp::Add, 5, Assoc::Left),
+        Kind::Minus => (BinaryOp::Sub, 5, Assoc::Left),
+        Kind::Star => (BinaryOp::Mul, 6, Assoc::Left),
+        Kind::Slash => (BinaryOp::Div, 6, Assoc::Left),
+        Kind::Percent => (BinaryOp::Rem, 6, Assoc::Left),
+        Kind::StarStar => (BinaryOp::Pow, POW_PRECEDENCE, Assoc::Right),
+        _ => return None,
+    };
+    Some(op)
+}
+
+// Exponentiation binds tighter than unary operators, so `-2 ** 2` is `-(2 ** 2)`
+const POW_PRECEDENCE: u8 = 7;
+
+struct Parser<'tokens> {
+    // The offset reported for errors at the end of the tokens
+    end: usize,
+    tokens: Vec<&'tokens Token>,
+    next: usize,
+}
+
+pub fn parse(tokens: &Tokens) -> ParseResult<Program> {
+    let parser = Parser {
+        end: tokens.source.len(),
+        tokens: tokens
+            .tokens
+            .iter()
+            .filter(|token| !matches!(token.kind, Kind::Comment(_)))
+            .collect(),
+        next: 0,
+    };
+    parser.program()
+}
+
+impl Parser<'_> {
+    fn peek_nth(&self, n: usize) -> Option<&Kind> {
+        self.tokens.get(self.next + n).map(|token| &token.kind)
+    }
+
+    fn peek(&self) -> Option<&Kind> {
+        self.peek_nth(0)
+    }
+
+    fn advance(&mut self) -> &Token {
+        let token = self.tokens[self.next];
+        self.next += 1;
+        token
+    }
+
+    fn eat(&mut self, kind: &Kind) -> bool {
+        if self.peek() == Some(kind) {
+            self.next += 1;
+            return true;
+        }
+        false
+    }
+
+    fn expect(&mut self, kind: Kind) -> ParseResult<SourceLocation> {
+        if self.peek() != Some(&kind) {
+            return Err(self.error(&kind.to_string()));
+        }
+        Ok(self.advance().location.clone())
+    }
+
+    fn error(&self, expected: &str) -> Diagnostic {
+        match self.tokens.get(self.next) {
+            Some(token) => Diagnostic::error(
+                format!("expected {expected}, found {}", token.kind),
+                token.location.clone(),
+            ),
+            None => Diagnostic::error(
+                format!("expected {expected}, found end of file"),
+                SourceLocation(self.end..self.end),
+            ),
+        }
+    }
+
+    fn skip_newlines(&mut self) {
+        while self.eat(&Kind::Newline) {}
+    }
+
+    fn program(mut self) -> ParseResult<Program> {
+        let stmts = self.stmts()?;
+        if self.peek().is_some() {
+            return Err(self.error("statement"));
+        }
+        Ok(Program { stmts })
+    }
+
+    // Parses newline separated statements up to, but not including, a closing
+    // brace or the end of the file.
+    fn stmts(&mut self) -> ParseResult<Vec<Stmt>> {
+        let mut stmts = Vec::new();
+        loop {
+            self.skip_newlines();
+            if matches!(self.peek(), None | Some(Kind::BraceClose)) {
+                return Ok(stmts);
+            }
+            stmts.push(self.stmt()?);
+            if !matches!(
+                self.peek(),
+                None | Some(Kind::BraceClose) | Some(Kind::Newline)
+            ) {
+                return Err(self.error(&Kind::Newline.to_string()));
+            }
+        }
+    }
+
+    fn stmt(&mut self) -> ParseResult<Stmt> {
+        if self.eat(&Kind::Let) {
+            let name = self.name()?;
+            self.expect(Kind::Equal)?;
+            let value = self.expr()?;
+            return Ok(Stmt::Let { name, value });
+        }
+        if let (Some(Kind::Name(_)), Some(Kind::Equal)) = (self.peek(), self.peek_nth(1)) {
+            if self.is_union() {
+                return self.union();
+            }
+            let name = self.name()?;
+            self.advance();
+            let value = self.expr()?;
+            return Ok(Stmt::Binding { name, value });
+        }
+        let expr = self.expr()?;
+        if self.eat(&Kind::Equal) {
+            if !matches!(expr.kind, ExprKind::Index { .. }) {
+                return Err(Diagnostic::error(
+                    "invalid assignment target",
+                    expr.location,
+                ));
+            }
+            let value = self.expr()?;
+            return Ok(Stmt::Assign {
+                target: expr,
+                value,
+            });
+        }
+        Ok(Stmt::Expr(expr))
+    }
+
+    // Looks ahead from `name =` for a `|` before the end of the statement,
+    // which makes it a union declaration.
+    fn is_union(&self) -> bool {
+        let mut n = 2;
+        let mut depth = 0;
+        loop {
+            match self.peek_nth(n) {
+                Some(Kind::Pipe) if depth == 0 => return true,
+                Some(Kind::ParenOpen | Kind::BraceOpen | Kind::BracketOpen) => depth += 1,
+                Some(Kind::ParenClose | Kind::BraceClose | Kind::BracketClose) if depth > 0 => {
+                    depth -= 1
+                }
+                // A `|` may start the next line
+                Some(Kind::Newline) if depth == 0 => {
+                    while self.peek_nth(n + 1) == Some(&Kind::Newline) {
+                        n += 1;
+                    }
+                    if self.peek_nth(n + 1) != Some(&Kind::Pipe) {
+                        return false;
+                    }
+                }
+                None | Some(Kind::ParenClose | Kind::BraceClose | Kind::BracketClose) => {
+                    return false
+                }
+                _ => {}
+            }
+            n += 1;
+        }
+    }
+
+    // Eats a `|`, which may be on a following line.
+    fn eat_pipe(&mut self) -> bool {
+        let mut n = 0;
+        while self.peek_nth(n) == Some(&Kind::Newline) {
+            n += 1;
+        }
+        if self.peek_nth(n) == Some(&Kind::Pipe) {
+            self.next += n + 1;
+            return true;
+        }
+        false
+    }
+
+    // Parses `Name = Variant(field, ...) | Variant | ...`, where the first
+    // variant may also be preceded by a `|`.
+    fn union(&mut self) -> ParseResult<Stmt> {
+        let name = self.name()?;
+        self.expect(Kind::Equal)?;
+        self.eat_pipe();
+        let mut variants = Vec::new();
+        loop {
+            let variant = self.name()?;
+            let mut fields = Vec::new();
+            if self.eat(&Kind::ParenOpen) {
+                while self.peek() != Some(&Kind::ParenClose) {
+                    fields.push(self.name()?);
+                    if !self.eat(&Kind::Comma) {
+                        break;
+                    }
+                }
+                self.expect(Kind::ParenClose)?;
+            }
+            variants.push(Variant {
+                name: variant,
+                fields,
+            });
+            if !self.eat_pipe() {
+                return Ok(Stmt::Union { name, variants });
+            }
+        }
+    }
+
+    fn name(&mut self) -> ParseResult<Name> {
+        let Some(Kind::Name(name)) = self.peek() else {
+            return Err(self.error("name"));
+        };
+        let name = name.clone();
+        let location = self.advance().location.clone();
+        Ok(Name { name, location })
+    }
+
+    fn expr(&mut self) -> ParseResult<Expr> {
+        self.binary(0)
+    }
+
+    // Parses a chain of binary operators that bind at least as tightly as
+    // `min_precedence`.
+    fn binary(&mut self, min_precedence: u8) -> ParseResult<Expr> {
+        let mut lhs = self.unary()?;
+        while let Some((op, precedence, assoc)) = self.peek().and_then(binary_op) {
+            if precedence < min_precedence {
+                break;
+            }
+            self.advance();
+            let rhs = match assoc {
+                Assoc::Left => self.binary(precedence + 1)?,
+                Assoc::Right => self.binary(precedence)?,
+            };
+            lhs = Expr {
+                location: lhs.location.to(&rhs.location),
+                kind: ExprKind::Binary {
+                    op,
+                    lhs: Box::new(lhs),
+                    rhs: Box::new(rhs),
+                },
+            };
+        }
+        Ok(lhs)
+    }
+
+    fn unary(&mut self) -> ParseResult<Expr> {
+        let op = match self.peek() {
+            Some(Kind::Minus) => UnaryOp::Neg,
+            Some(Kind::Bang) => UnaryOp::Not,
+            _ => return self.postfix(),
+        };
+        let start = self.advance().location.clone();
+        let operand = self.binary(POW_PRECEDENCE)?;
+        Ok(Expr {
+            location: start.to(&operand.location),
+            kind: ExprKind::Unary {
+                op,
+                operand: Box::new(operand),
+            },
+        })
+    }
+
+    fn postfix(&mut self) -> ParseResult<Expr> {
+        let mut expr = self.primary()?;
+        loop {
+            match self.peek() {
+                // Named arguments construct a record
+                Some(Kind::ParenOpen)
+                    if matches!(
+                        (self.peek_nth(1), self.peek_nth(2)),
+                        (Some(Kind::Name(_)), Some(Kind::Colon))
+                    ) =>
+                {
+                    self.advance();
+                    let (fields, end) = self.entries(Kind::ParenClose, Self::name)?;
+                    expr = Expr {
+                        location: expr.location.to(&end),
+                        kind: ExprKind::Construct {
+                            callee: Box::new(expr),
+                            fields,
+                        },
+                    };
+                }
+                Some(Kind::ParenOpen) => {
+                    let (args, end) = self.delimited(Kind::ParenOpen, Kind::ParenClose)?;
+                    expr = Expr {
+                        location: expr.location.to(&end),
+                        kind: ExprKind::Call {
+                            callee: Box::new(expr),
+                            args,
+                        },
+                    };
+                }
+                Some(Kind::BracketOpen) => {
+                    self.advance();
+                    let index = self.expr()?;
+                    let end = self.expect(Kind::BracketClose)?;
+                    expr = Expr {
+                        location: expr.location.to(&end),
+                        kind: ExprKind::Index {
+                            target: Box::new(expr),
+                            index: Box::new(index),
+                        },
+                    };
+                }
+                Some(Kind::Dot) => {
+                    self.advance();
+                    let field = self.name()?;
+                    expr = Expr {
+                        location: expr.location.to(&field.location),
+                        kind: ExprKind::Field {
+                            target: Box::new(expr),
+                            field,
+                        },
+                    };
+                }
+                Some(Kind::With) => {
+                    self.advance();
+                    self.expect(Kind::BraceOpen)?;
+                    let (fields, end) = self.entries(Kind::BraceClose, Self::name)?;
+                    expr = Expr {
+                        location: expr.location.to(&end),
+                        kind: ExprKind::Update {
+                            target: Box::new(expr),
+                            fields,
+                        },
+                    };
+                }
+                _ => return Ok(expr),
+            }
+        }
+    }
+
+    // Parses a comma separated list of expressions between `open` and `close`,
+    // such as an argument list, and returns it along with the location of
+    // `close`.
+    fn delimited(&mut self, open: Kind, close: Kind) -> ParseResult<(Vec<Expr>, SourceLocation)> {
+        self.expect(open)?;
+        let mut exprs = Vec::new();
+        while self.peek() != Some(&close) {
+            exprs.push(self.expr()?);
+            if !self.eat(&Kind::Comma) {
+                break;
+            }
+        }
+        let end = self.expect(close)?;
+        Ok((exprs, end))
+    }
+
+    fn primary(&mut self) -> ParseResult<Expr> {
+        match self.peek() {
+            Some(&Kind::Integer(n)) => {
+                let location = self.advance().location.clone();
+                Ok(Expr {
+                    kind: ExprKind::Integer(n),
+                    location,
+                })
+            }
+            Some(&Kind::Float(x)) => {
+                let location = self.advance().location.clone();
+                Ok(Expr {
+                    kind: ExprKind::Float(x),
+                    location,
+                })
+            }
+            Some(kind @ (Kind::True | Kind::False)) => {
+                let b = *kind == Kind::True;
+                let location = self.advance().location.clone();
+                Ok(Expr {
+                    kind: ExprKind::Bool(b),
+                    location,
+                })
+            }
+            Some(Kind::String(s)) => {
+                let s = s.clone();
+                let location = self.advance().location.clone();
+                Ok(Expr {
+                    kind: ExprKind::String(s),
+                    location,
+                })
+            }
+            Some(Kind::Template(_)) => self.template(),
+            Some(Kind::If) => self.if_expr(),
+            Some(Kind::Match) => self.match_expr(),
+            Some(Kind::While | Kind::For) => self.loop_expr(None),
+            Some(Kind::Label(_)) => {
+                let label = self.label()?;
+                self.expect(Kind::Colon)?;
+                match self.peek() {
+                    Some(Kind::While | Kind::For) => self.loop_expr(Some(label)),
+                    _ => Err(self.error("loop")),
+                }
+            }
+            Some(kind @ (Kind::Break | Kind::Continue)) => {
+                let is_break = *kind == Kind::Break;
+                let mut location = self.advance().location.clone();
+                let label = match self.peek() {
+                    Some(Kind::Label(_)) => {
+                        let label = self.label()?;
+                        location = location.to(&label.location);
+                        Some(label)
+                    }
+                    _ => None,
+                };
+                let kind = if is_break {
+                    ExprKind::Break(label)
+                } else {
+                    ExprKind::Continue(label)
+                };
+                Ok(Expr { kind, location })
+            }
+            Some(Kind::Name(_)) => {
+                let Name { name, location } = self.name()?;
+                Ok(Expr {
+                    kind: ExprKind::Name(name),
+                    location,
+                })
+            }
+            Some(Kind::ParenOpen) if self.is_lambda() => self.lambda(),
+            Some(Kind::ParenOpen) => {
+                let start = self.advance().location.clone();
+                let expr = self.expr()?;
+                let end = self.expect(Kind::ParenClose)?;
+                Ok(Expr {
+                    kind: expr.kind,
+                    location: start.to(&end),
+                })
+            }
+            Some(Kind::Record) => self.record_type(),
+            Some(Kind::BraceOpen) if self.is_map() => self.map(),
+            Some(Kind::BraceOpen) => self.block(),
+            Some(Kind::BracketOpen) => {
+                let start = self.tokens[self.next].location.clone();
+                let (items, end) = self.delimited(Kind::BracketOpen, Kind::BracketClose)?;
+                Ok(Expr {
+                    kind: ExprKind::List(items),
+                    location: start.to(&end),
+                })
+            }
+            _ => Err(self.error("expression")),
+        }
+    }
+
+    // Looks ahead from an opening parenthesis for a parameter list followed by
+    // a fat arrow.
+    fn is_lambda(&self) -> bool {
+        let mut n = 1;
+        if self.peek_nth(n) != Some(&Kind::ParenClose) {
+            loop {
+                if !matches!(self.peek_nth(n), Some(Kind::Name(_))) {
+                    return false;
+                }
+                n += 1;
+                match self.peek_nth(n) {
+                    Some(Kind::Comma) => n += 1,
+                    Some(Kind::ParenClose) => break,
+                    _ => return false,
+                }
+            }
+        }
+        self.peek_nth(n + 1) == Some(&Kind::FatArrow)
+    }
+
+    // Looks ahead from an opening brace for a colon before the end of the
+    // first statement, which makes it a map rather than a block. `{}` is an
+    // empty block and `{:}` an empty map.
+    fn is_map(&self) -> bool {
+        let mut n = 1;
+        while self.peek_nth(n) == Some(&Kind::Newline) {
+            n += 1;
+        }
+        let mut depth = 0;
+        loop {
+            match self.peek_nth(n) {
+                Some(Kind::Colon) if depth == 0 => return true,
+                Some(Kind::ParenOpen | Kind::BraceOpen | Kind::BracketOpen) => depth += 1,
+                Some(Kind::ParenClose | Kind::BraceClose | Kind::BracketClose) if depth > 0 => {
+                    depth -= 1
+                }
+                Some(Kind::Newline) if depth > 0 => {}
+                None | Some(Kind::ParenClose | Kind::BraceClose | Kind::BracketClose) => {
+                    return false
+                }
+                Some(Kind::Newline | Kind::Equal) => return false,
+                _ => {}
+            }
+            n += 1;
+        }
+    }
+
+    fn map(&mut self) -> ParseResult<Expr> {
+        let start = self.expect(Kind::BraceOpen)?;
+        self.skip_newlines();
+        let (entries, end) = if self.eat(&Kind::Colon) {
+            self.skip_newlines();
+            (Vec::new(), self.expect(Kind::BraceClose)?)
+        } else {
+            self.entries(Kind::BraceClose, Self::expr)?
+        };
+        Ok(Expr {
+            kind: ExprKind::Map(entries),
+            location: start.to(&end),
+        })
+    }
+
+    // Parses `key: value` pairs up to `close`, separated by commas, newlines or
+    // both, and returns them along with the location of `close`.
+    fn entries<K>(
+        &mut self,
+        close: Kind,
+        key: fn(&mut Self) -> ParseResult<K>,
+    ) -> ParseResult<(Vec<(K, Expr)>, SourceLocation)> {
+        let mut entries = Vec::new();
+        self.skip_newlines();
+        while self.peek() != Some(&close) {
+            let k = key(self)?;
+            self.expect(Kind::Colon)?;
+            self.skip_newlines();
+            let value = self.expr()?;
+            entries.push((k, value));
+            let separated = self.eat(&Kind::Comma) | self.eat(&Kind::Newline);
+            self.skip_newlines();
+            if !separated {
+                break;
+            }
+        }
+        let end = self.expect(close)?;
+        Ok((entries, end))
+    }
+
+    // Parses `record { x, y }`, whose fields may be separated by commas,
+    // newlines or both.
+    fn record_type(&mut self) -> ParseResult<Expr> {
+        let start = self.expect(Kind::Record)?;
+        self.expect(Kind::BraceOpen)?;
+        let mut fields = Vec::new();
+        self.skip_newlines();
+        while self.peek() != Some(&Kind::BraceClose) {
+            fields.push(self.name()?);
+            let separated = self.eat(&Kind::Comma) | self.eat(&Kind::Newline);
+            self.skip_newlines();
+            if !separated {
+                break;
+            }
+        }
+        let end = self.expect(Kind::BraceClose)?;
+        Ok(Expr {
+            kind: ExprKind::RecordType(fields),
+            location: start.to(&end),
+        })
+    }
+
+    fn lambda(&mut self) -> ParseResult<Expr> {
+        let start = self.expect(Kind::ParenOpen)?;
+        let mut params = Vec::new();
+        while self.peek() != Some(&Kind::ParenClose) {
+            params.push(self.name()?);
+            if !self.eat(&Kind::Comma) {
+                break;
+            }
+        }
+        self.expect(Kind::ParenClose)?;
+        self.expect(Kind::FatArrow)?;
+        let body = self.expr()?;
+        Ok(Expr {
+            location: start.to(&body.location),
+            kind: ExprKind::Lambda {
+                params,
+                body: Box::new(body),
+            },
+        })
+    }
+
+    // Parses a string literal with interpolations, each of which must hold a
+    // single expression.
+    fn template(&mut self) -> ParseResult<Expr> {
+        let token = self.advance();
+        let Kind::Template(parts) = &token.kind else {
+            unreachable!();
+        };
+        let location = token.location.clone();
+        let mut exprs = Vec::new();
+        for part in parts {
+            match part {
+                TemplatePart::Text(text) => exprs.push(Expr {
+                    kind: ExprKind::String(text.clone()),
+                    location: location.clone(),
+                }),
+                TemplatePart::Code(tokens, code) => {
+                    let mut parser = Parser {
+                        end: code.0.end,
+                        tokens: tokens.iter().collect(),
+                        next: 0,
+                    };
+                    exprs.push(parser.expr()?);
+                    if parser.peek().is_some() {
+                        return Err(parser.error(&Kind::BraceClose.to_string()));
+                    }
+                }
+            }
+        }
+        Ok(Expr {
+            kind: ExprKind::Template(exprs),
+            location,
+        })
+    }
+
+    // Parses `if cond { ... }`, optionally followed by `else { ... }` or
+    // `else if ...` on the same line as the closing brace.
+    fn if_expr(&mut self) -> ParseResult<Expr> {
+        let start = self.expect(Kind::If)?;
+        let cond = self.expr()?;
+        let then = self.block()?;
+        let mut end = then.location.clone();
+        let mut otherwise = None;
+        if self.eat(&Kind::Else) {
+            let expr = if self.peek() == Some(&Kind::If) {
+                self.if_expr()?
+            } else {
+                self.block()?
+            };
+            end = expr.location.clone();
+            otherwise = Some(Box::new(expr));
+        }
+        Ok(Expr {
+            location: start.to(&end),
+            kind: ExprKind::If {
+                cond: Box::new(cond),
+                then: Box::new(then),
+                otherwise,
+            },
+        })
+    }
+
+    fn label(&mut self) -> ParseResult<Name> {
+        match self.peek() {
+            Some(Kind::Label(name)) => {
+                let name = name.clone();
+                let location = self.advance().location.clone();
+                Ok(Name { name, location })
+            }
+            _ => Err(self.error("label")),
+        }
+    }
+
+    // Parses `while cond { ... }` or `for x in items { ... }`, where `for`
+    // may bind two names and may count over a range as in `for i in 0..n`.
+    fn loop_expr(&mut self, label: Option<Name>) -> ParseResult<Expr> {
+        let start = label
+            .as_ref()
+            .map(|label| label.location.clone())
+            .unwrap_or_else(|| self.tokens[self.next].location.clone());
+        if self.eat(&Kind::While) {
+            let cond = self.expr()?;
+            let body = self.block()?;
+            return Ok(Expr {
+                location: start.to(&body.location),
+                kind: ExprKind::While {
+                    label,
+                    cond: Box::new(cond),
+                    body: Box::new(body),
+                },
+            });
+        }
+        self.expect(Kind::For)?;
+        let mut names = vec![self.name()?];
+        if self.eat(&Kind::Comma) {
+            names.push(self.name()?);
+        }
+        self.expect(Kind::In)?;
+        let iterable = self.expr()?;
+        let end = match self.eat(&Kind::DotDot) {
+            true => Some(Box::new(self.expr()?)),
+            false => None,
+        };
+        let body = self.block()?;
+        Ok(Expr {
+            location: start.to(&body.location),
+            kind: ExprKind::For {
+                label,
+                names,
+                iterable: Box::new(iterable),
+                end,
+                body: Box::new(body),
+            },
+        })
+    }
+
+    // Parses `match value { pattern => expr, ... }`. Each pattern may be
+    // followed by an `if` guard, and arms are separated by commas, newlines or
+    // both.
+    fn match_expr(&mut self) -> ParseResult<Expr> {
+        let start = self.expect(Kind::Match)?;
+        let scrutinee = self.expr()?;
+        self.expect(Kind::BraceOpen)?;
+        let mut arms = Vec::new();
+        self.skip_newlines();
+        while self.peek() != Some(&Kind::BraceClose) {
+            let pattern = self.pattern()?;
+            let guard = if self.eat(&Kind::If) {
+                Some(self.expr()?)
+            } else {
+                None
+            };
+            self.expect(Kind::FatArrow)?;
+            self.skip_newlines();
+            let body = self.expr()?;
+            arms.push(Arm {
+                pattern,
+                guard,
+                body,
+            });
+            let separated = self.eat(&Kind::Comma) | self.eat(&Kind::Newline);
+            self.skip_newlines();
+            if !separated {
+                break;
+            }
+        }
+        let end = self.expect(Kind::BraceClose)?;
+        Ok(Expr {
+            kind: ExprKind::Match {
+                scrutinee: Box::new(scrutinee),
+                arms,
+            },
+            location: start.to(&end),
+        })
+    }
+
+    // Parses a pattern. A name followed by parentheses or starting with an
+    // uppercase letter is a union variant, and any other name binds whatever
+    // it matches.
+    fn pattern(&mut self) -> ParseResult<Pattern> {
+        let Some(start) = self
+            .tokens
+            .get(self.next)
+            .map(|token| token.location.clone())
+        else {
+            return Err(self.error("pattern"));
+        };
+        let kind = match self.peek() {
+            Some(&Kind::Integer(n)) => {
+                self.advance();
+                PatternKind::Integer(n)
+            }
+            Some(Kind::Minus) => {
+                self.advance();
+                let Some(&Kind::Integer(n)) = self.peek() else {
+                    return Err(self.error("integer"));
+                };
+                self.advance();
+                PatternKind::Integer(-n)
+            }
+            Some(kind @ (Kind::True | Kind::False)) => {
+                let b = *kind == Kind::True;
+                self.advance();
+                PatternKind::Bool(b)
+            }
+            Some(Kind::String(s)) => {
+                let s = s.clone();
+                self.advance();
+                PatternKind::String(s)
+            }
+            Some(Kind::Name(_)) => {
+                let name = self.name()?;
+                if self.eat(&Kind::ParenOpen) {
+                    let mut fields = Vec::new();
+                    while self.peek() != Some(&Kind::ParenClose) {
+                        fields.push(self.pattern()?);
+                        if !self.eat(&Kind::Comma) {
+                            break;
+                        }
+                    }
+                    self.expect(Kind::ParenClose)?;
+                    PatternKind::Variant { name, fields }
+                } else if name.name == "_" {
+                    PatternKind::Wildcard
+                } else if name.name.starts_with(char::is_uppercase) {
+                    PatternKind::Variant {
+                        name,
+                        fields: Vec::new(),
+                    }
+                } else {
+                    PatternKind::Binding(name.name)
+                }
+            }
+            Some(Kind::BracketOpen) => {
+                self.advance();
+                let mut items = Vec::new();
+                let mut rest = None;
+                while self.peek() != Some(&Kind::BracketClose) {
+                    if self.peek() == Some(&Kind::DotDot) {
+                        rest = Some(Box::new(self.rest_pattern()?));
+                        self.eat(&Kind::Comma);
+                        break;
+                    }
+                    items.push(self.pattern()?);
+                    if !self.eat(&Kind::Comma) {
+                        break;
+                    }
+                }
+                self.expect(Kind::BracketClose)?;
+                PatternKind::List { items, rest }
+            }
+            _ => return Err(self.error("pattern")),
+        };
+        let end = self.tokens[self.next - 1].location.clone();
+        Ok(Pattern {
+            kind,
+            location: start.to(&end),
+        })
+    }
+
+    // Parses `..` or `..name` at the end of a list pattern.
+    fn rest_pattern(&mut self) -> ParseResult<Pattern> {
+        let location = self.expect(Kind::DotDot)?;
+        if !matches!(self.peek(), Some(Kind::Name(_))) {
+            return Ok(Pattern {
+                kind: PatternKind::Wildcard,
+                location,
+            });
+        }
+        let name = self.name()?;
+        let kind = if name.name == "_" {
+            PatternKind::Wildcard
+        } else {
+            PatternKind::Binding(name.name)
+        };
+        Ok(Pattern {
+            kind,
+            location: location.to(&name.location),
+        })
+    }
+
+    fn block(&mut self) -> ParseResult<Expr> {
+        let start = self.expect(Kind::BraceOpen)?;
+        let stmts = self.stmts()?;
+        let end = self.expect(Kind::BraceClose)?;
+        Ok(Expr {
+            kind: ExprKind::Block(stmts),
+            location: start.to(&end),
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn parse_source(source: &str) -> ParseResult<String> {
+        let tokens = Tokens::from_source(source).unwrap();
+        Ok(parse(&tokens)?.to_string())
+    }
+
+    #[test]
+    fn test_script() {
+        let source = "x = () => {\n    1 + 2\n}\n3 + 3\n";
+        assert_eq!(
+            parse_source(source).unwrap(),
+            "x = () => { (1 + 2) }\n(3 + 3)"
+        );
+        assert_eq!(
+            parse_source("let x = 1\ny = { let x = x + 1\nx }").unwrap(),
+            "let x = 1\ny = { let x = (x + 1); x }"
+        );
+        assert!(parse_source("let 1 = 2").is_err());
+    }
+
+    #[test]
+    fn test_calls() {
+        assert_eq!(
+            parse_source("adder = (x) => { (y) => { x + y } }\nadder(2)(1)").unwrap(),
+            "adder = (x) => { (y) => { (x + y) } }\nadder(2)(1)"
+        );
+        assert_eq!(
+            parse_source("f(1 + 2, (3 + 4) + g())").unwrap(),
+            "f((1 + 2), ((3 + 4) + g()))"
+        );
+    }
+
+    #[test]
+    fn test_precedence() {
+        assert_eq!(
+            parse_source("a || b && c == d < e + f * g ** h").unwrap(),
+            "(a || (b && (c == (d < (e + (f * (g ** h)))))))"
+        );
+        assert_eq!(
+            parse_source("a * b + c - d / e % f").unwrap(),
+            "(((a * b) + c) - ((d / e) % f))"
+        );
+        assert_eq!(
+            parse_source("2 ** 3 ** 2 - -2 ** 2").unwrap(),
+            "((2 ** (3 ** 2)) - (-(2 ** 2)))"
+        );
+        assert_eq!(
+            parse_source("!a == !(b != c) <= -f(x)").unwrap(),
+            "((!a) == ((!(b != c)) <= (-f(x))))"
+        );
+    }
+
+    #[test]
+    fn test_if() {
+        assert_eq!(
+            parse_source("x = if a < b { a } else if !c { true } else { false }").unwrap(),
+            "x = if (a < b) { a } else if (!c) { true } else { false }"
+        );
+        assert_eq!(
+            parse_source("if f(x) {\n    y\n}\nelse").err(),
+            Some(Diagnostic::error(
+                "expected expression, found <Else>",
+                SourceLocation(18..22)
+            ))
+        );
+    }
+
+    #[test]
+    fn test_templates() {
+        assert_eq!(
+            parse_source(r#""total: {a + b}, {"nested {c}"}\{""#).unwrap(),
+            r#""total: {(a + b)}, {"nested {c}"}\{""#
+        );
+        assert_eq!(
+            parse_source(r#"x = "a {1 +} b""#),
+            Err(Diagnostic::error(
+                "expected expression, found end of file",
+                SourceLocation(11..11)
+            ))
+        );
+        assert_eq!(
+            parse_source(r#"x = "a {b c}""#),
+            Err(Diagnostic::error(
+                "expected <BraceClose>, found <Name c>",
+                SourceLocation(10..11)
+            ))
+        );
+    }
+
+    #[test]
+    fn test_lists() {
+        assert_eq!(
+            parse_source("xs = [1, [2, 3],\n]\nxs[1][0] = f(xs)[0]").unwrap(),
+            "xs = [1, [2, 3]]\nxs[1][0] = f(xs)[0]"
+        );
+        assert_eq!(
+            parse_source("f(x) = 1"),
+            Err(Diagnostic::error(
+                "invalid assignment target",
+                SourceLocation(0..4)
+            ))
+        );
+    }
+
+    #[test]
+    fn test_maps() {
+        assert_eq!(
+            parse_source("m = {\n    \"a\": 1, b + 1: [2],\n    c: {:}\n}\n{ x }\nf({})").unwrap(),
+            "m = {\"a\": 1, (b + 1): [2], c: {:}}\n{ x }\nf({  })"
+        );
+        assert_eq!(
+            parse_source("{a: 1 b: 2}"),
+            Err(Diagnostic::error(
+                "expected <BraceClose>, found <Name b>",
+                SourceLocation(6..7)
+            ))
+        );
+    }
+
+    #[test]
+    fn test_records() {
+        let source = "Point = record {\n    x, y\n}\np = Point(x: 1, y: 2)\nq = p with { y: p.x }\nPoint(1, 2).x";
+        assert_eq!(
+            parse_source(source).unwrap(),
+            "Point = record { x, y }\np = Point(x: 1, y: 2)\nq = p with { y: p.x }\nPoint(1, 2).x"
+        );
+        assert_eq!(
+            parse_source("p.1"),
+            Err(Diagnostic::error(
+                "expected name, found <Integer 1>",
+                SourceLocation(2..3)
+            ))
+        );
+    }
+
+    #[test]
+    fn test_match() {
+        let source = "Shape = Circle(r) | Rect(w, h)\nOption =\n    | Some(x)\n    | None\n\
+            match f(x) {\n    Some([a, -1, ..rest]) if a > 0 => a,\n    None | _ => \"no\"\n}";
+        assert_eq!(
+            parse_source(source).err(),
+            Some(Diagnostic::error(
+                "expected <FatArrow>, found <Pipe>",
+                SourceLocation(128..129)
+            ))
+        );
+        let source = "Shape = Circle(r) | Rect(w, h)\nOption =\n    | Some(x)\n    | None\n\
+            match f(x) {\n    Some([a, -1, ..rest]) if a > 0 => a,\n    Rect(_, [..]) => true\n\n    None =>\n        \"no\" }";
+        assert_eq!(
+            parse_source(source).unwrap(),
+            "Shape = Circle(r) | Rect(w, h)\nOption = Some(x) | None\n\
+             match f(x) { Some([a, -1, ..rest]) if (a > 0) => a, Rect(_, [..]) => true, None => \"no\" }"
+        );
+    }
+
+    #[test]
+    fn test_loops() {
+        let source = "while i < 3 { i = i + 1 }
+\
+            'rows: for y in 0..h + 1 {\n    for k, v in m { if v { continue 'rows } else { break } }\n}";
+        assert_eq!(
+            parse_source(source).unwrap(),
+            "while (i < 3) { i = (i + 1) }\n\
+             'rows: for y in 0..(h + 1) { for k, v in m { if v { continue 'rows } else { break } } }"
+        );
+        assert_eq!(
+            parse_source("'outer: x").err(),
+            Some(Diagnostic::error(
+                "expected loop, found <Name x>",
+                SourceLocation(8..9)
+            ))
+        );
+        assert_eq!(
+            parse_source("for x y in z {}").err(),
+            Some(Diagnostic::error(
+                "expected <In>, found <Name y>",
+                SourceLocation(6..7)
+            ))
+        );
+    }
+
+    #[test]
+    fn test_errors() {
+        assert_eq!(
+            parse_source("f(1"),
+            Err(Diagnostic::error(
+                "expected <ParenClose>, found end of file",
+                SourceLocation(3..3)
+            ))
+        );
+        assert_eq!(
+            parse_source("x = 1\n1 + + 2"),
+            Err(Diagnostic::error(
+                "expected expression, found <Plus>",
+                SourceLocation(10..11)
+            ))
+        );
+        assert_eq!(
+            parse_source("1 2"),
+            Err(Diagnostic::error(
+                "expected <Newline>, found <Integer 2>",
+                SourceLocation(2..3)
+            ))
+        );
+    }
+}
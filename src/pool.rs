@@ -1,7 +1,16 @@
+use itertools::Itertools;
 use paste::paste;
 use std::collections::{HashMap, HashSet};
 
-use crate::{function::Function, object::Object, thread::Thread, value::Value};
+use crate::{
+    error::ErrorKind,
+    function::Function,
+    map::{Key, Map},
+    object::{List, Object, Upvalue},
+    record::{Record, Shape},
+    thread::Thread,
+    value::Value,
+};
 
 pub struct ObjectPool {
     objects: Vec<Object>,
@@ -24,12 +33,17 @@ impl ObjectPool {
         value
     }
 
-    pub fn compact(&mut self, root: Value) -> Value {
-        let Value::Object(root) = root else {
-            self.objects.clear();
-            return root;
-        };
-        let mut queue = vec![root];
+    /// Frees every object that can't be reached from `roots`, updating the
+    /// roots to where the objects they refer to have moved.
+    pub fn compact<'a>(&mut self, roots: impl IntoIterator<Item = &'a mut Value>) {
+        let mut roots: Vec<&mut usize> = roots
+            .into_iter()
+            .filter_map(|root| match root {
+                Value::Object(i) => Some(i),
+                _ => None,
+            })
+            .collect();
+        let mut queue: Vec<usize> = roots.iter().map(|root| **root).collect();
         // Recursively visit each reference
         let mut keep = HashSet::new();
         while let Some(i) = queue.pop() {
@@ -53,14 +67,150 @@ impl ObjectPool {
                 *i = *mapping.get(i).unwrap();
             }
         }
-        Value::Object(*mapping.get(&root).unwrap())
+        for root in &mut roots {
+            **root = *mapping.get(&**root).unwrap();
+        }
+    }
+
+    pub fn type_name(&self, value: &Value) -> &'static str {
+        match value {
+            Value::Nil => "nil",
+            Value::Bool(_) => "bool",
+            Value::Integer(_) => "integer",
+            Value::Float(_) => "float",
+            Value::Builtin(_) => "function",
+            Value::Object(i) => match self.objects.get(*i) {
+                Some(Object::Function(_)) => "function",
+                Some(Object::List(_)) => "list",
+                Some(Object::Map(_)) => "map",
+                Some(Object::Record(_)) => "record",
+                Some(Object::Shape(_)) => "record type",
+                Some(Object::String(_)) => "string",
+                Some(Object::Thread(_)) => "thread",
+                Some(Object::Upvalue(_)) => "upvalue",
+                None => "invalid object",
+            },
+        }
+    }
+
+    /// Compares values for `==`. Numbers are equal if they're numerically
+    /// equal, even if one is an integer and the other a float. Strings are
+    /// equal if they have the same contents, other objects are only equal to
+    /// themselves, and values of different types are never equal.
+    pub fn equal(&self, a: &Value, b: &Value) -> bool {
+        match (a, b) {
+            (Value::Nil, Value::Nil) => true,
+            (Value::Bool(a), Value::Bool(b)) => a == b,
+            (Value::Integer(a), Value::Integer(b)) => a == b,
+            (Value::Integer(a), Value::Float(b)) | (Value::Float(b), Value::Integer(a)) => {
+                *a as f64 == *b
+            }
+            (Value::Float(a), Value::Float(b)) => a == b,
+            (Value::Builtin(a), Value::Builtin(b)) => a == b,
+            (Value::Object(i), Value::Object(j)) => match (self.string(*a), self.string(*b)) {
+                (Ok(a), Ok(b)) => a == b,
+                _ => i == j,
+            },
+            _ => false,
+        }
+    }
+
+    pub fn integer(&self, value: Value) -> Result<i64, ErrorKind> {
+        match value {
+            Value::Integer(n) => Ok(n),
+            _ => Err(ErrorKind::TypeMismatch {
+                expected: "integer",
+                found: self.type_name(&value),
+            }),
+        }
+    }
+
+    /// Returns the key a value is stored under in a map. Only nil, booleans,
+    /// integers and strings can be used as keys.
+    pub fn key(&self, value: Value) -> Result<Key, ErrorKind> {
+        match value {
+            Value::Nil => Ok(Key::Nil),
+            Value::Bool(b) => Ok(Key::Bool(b)),
+            Value::Integer(n) => Ok(Key::Integer(n)),
+            _ => match self.string(value) {
+                Ok(string) => Ok(Key::String(string.clone())),
+                Err(_) => Err(ErrorKind::Unhashable {
+                    found: self.type_name(&value),
+                }),
+            },
+        }
     }
 
     pub fn to_string(&self, value: &Value) -> String {
+        self.format(value, false, &mut Vec::new())
+    }
+
+    /// Like `to_string`, but quotes strings so they can be told apart from
+    /// other values.
+    pub fn repr(&self, value: &Value) -> String {
+        self.format(value, true, &mut Vec::new())
+    }
+
+    // Formats a value, quoting strings if `quote` is set. Values inside lists
+    // and maps are always quoted. `containers` holds the lists and maps being
+    // formatted, so that one containing itself prints as `[...]` or `{...}`
+    // rather than recursing forever.
+    fn format(&self, value: &Value, quote: bool, containers: &mut Vec<usize>) -> String {
         match value {
-            Value::Nil => format!("Nil"),
+            Value::Nil => "Nil".to_string(),
+            Value::Bool(b) => format!("{b}"),
             Value::Integer(n) => format!("{n}"),
-            Value::Object(i) => format!("{}", &self.objects[*i]),
+            // Debug formatting is the shortest representation that parses
+            // back to the same float, and always has a decimal point or
+            // exponent so floats can't be mistaken for integers.
+            Value::Float(x) => format!("{x:?}"),
+            Value::Builtin(builtin) => format!("builtin {}", builtin.name()),
+            Value::Object(i) => match &self.objects[*i] {
+                Object::String(string) if quote => format!("{string:?}"),
+                Object::List(_) if containers.contains(i) => "[...]".to_string(),
+                Object::Map(_) if containers.contains(i) => "{...}".to_string(),
+                Object::List(items) => {
+                    containers.push(*i);
+                    let items = items
+                        .iter()
+                        .map(|item| self.format(item, true, containers))
+                        .join(", ");
+                    containers.pop();
+                    format!("[{items}]")
+                }
+                Object::Map(map) if map.len() == 0 => "{:}".to_string(),
+                Object::Map(map) => {
+                    containers.push(*i);
+                    let entries = map
+                        .keys
+                        .iter()
+                        .zip(&map.values)
+                        .map(|(key, value)| {
+                            let key = self.format(key, true, containers);
+                            format!("{key}: {}", self.format(value, true, containers))
+                        })
+                        .join(", ");
+                    containers.pop();
+                    format!("{{{entries}}}")
+                }
+                Object::Record(record) => match self.shape(record.shape) {
+                    // Such as a union variant without fields
+                    Ok(shape) if shape.fields.is_empty() => shape.name.clone(),
+                    Ok(shape) => {
+                        let fields = shape
+                            .fields
+                            .iter()
+                            .zip(&record.fields)
+                            .map(|(field, value)| {
+                                format!("{field}: {}", self.format(value, true, containers))
+                            })
+                            .join(", ");
+                        format!("{}({fields})", shape.name)
+                    }
+                    Err(_) => format!("{}", self.objects[*i]),
+                },
+                object => format!("{object}"),
+            },
         }
     }
 }
@@ -68,22 +218,35 @@ impl ObjectPool {
 macro_rules! decl_getters {
     ($($kind:tt)*) => {
         paste!{
+            #[allow(dead_code)]
             impl<'pool> ObjectPool {
-                pub fn [<$($kind)*:lower>](&'pool self, value: Value) -> &'pool $($kind)* {
+                pub fn [<$($kind)*:lower>](&'pool self, value: Value) -> Result<&'pool $($kind)*, ErrorKind> {
+                    let found = self.type_name(&value);
                     if let Value::Object(i) = value {
-                        if let Object::$($kind)*(kind) = &self.objects[i] {
-                            return kind;
+                        match self.objects.get(i) {
+                            Some(Object::$($kind)*(kind)) => return Ok(kind),
+                            None => return Err(ErrorKind::InvalidObject { index: i }),
+                            _ => {}
                         }
                     }
-                    panic!(std::stringify!(value is not a [<$($kind)*:lower>]));
+                    Err(ErrorKind::TypeMismatch {
+                        expected: std::stringify!([<$($kind)*:lower>]),
+                        found,
+                    })
                 }
-                pub fn [<$($kind)*:lower _mut>](&'pool mut self, value: Value) -> &'pool mut $($kind)* {
+                pub fn [<$($kind)*:lower _mut>](&'pool mut self, value: Value) -> Result<&'pool mut $($kind)*, ErrorKind> {
+                    let found = self.type_name(&value);
                     if let Value::Object(i) = value {
-                        if let Object::$($kind)*(kind) = &mut self.objects[i] {
-                            return kind;
+                        match self.objects.get_mut(i) {
+                            Some(Object::$($kind)*(kind)) => return Ok(kind),
+                            None => return Err(ErrorKind::InvalidObject { index: i }),
+                            _ => {}
                         }
                     }
-                    panic!(std::stringify!(value is not a [<$($kind)*:lower>]));
+                    Err(ErrorKind::TypeMismatch {
+                        expected: std::stringify!([<$($kind)*:lower>]),
+                        found,
+                    })
                 }
             }
         }
@@ -91,4 +254,10 @@ macro_rules! decl_getters {
 }
 
 decl_getters!(Function);
+decl_getters!(List);
+decl_getters!(Map);
+decl_getters!(Record);
+decl_getters!(Shape);
+decl_getters!(String);
 decl_getters!(Thread);
+decl_getters!(Upvalue);
@@ -0,0 +1,22 @@
+use crate::value::Value;
+
+/// A record type: its name and the names of its fields, which every record of
+/// the type shares. A field's position here is its slot in the record.
+#[derive(Clone)]
+pub struct Shape {
+    pub name: String,
+    pub fields: Vec<String>,
+}
+
+impl Shape {
+    pub fn slot(&self, field: &str) -> Option<usize> {
+        self.fields.iter().position(|name| name == field)
+    }
+}
+
+#[derive(Clone)]
+pub struct Record {
+    /// The record's `Shape` object.
+    pub shape: Value,
+    pub fields: Vec<Value>,
+}
@@ -0,0 +1,148 @@
+use std::fmt::Display;
+
+/// A 1-based line and column in a source file.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Position {
+    pub line: usize,
+    pub column: usize,
+}
+
+impl Display for Position {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}:{}", self.line, self.column)
+    }
+}
+
+/// Converts the byte offsets held by `SourceLocation`s into lines and columns.
+pub struct SourceMap<'source> {
+    source: &'source str,
+    // The byte offset of the first character of each line
+    line_starts: Vec<usize>,
+    // The offset of each character longer than a byte, with the number of
+    // bytes by which it and the characters before it exceed their length in
+    // chars and in UTF-16 code units, so columns can be found by a search
+    multi_byte: Vec<(usize, usize, usize)>,
+}
+
+impl<'source> SourceMap<'source> {
+    pub fn new(source: &'source str) -> Self {
+        let line_starts = [0]
+            .into_iter()
+            .chain(source.match_indices('\n').map(|(i, _)| i + 1))
+            .collect();
+        let (mut chars, mut utf16) = (0, 0);
+        let multi_byte = source
+            .char_indices()
+            .filter(|(_, c)| c.len_utf8() > 1)
+            .map(|(i, c)| {
+                chars += c.len_utf8() - 1;
+                utf16 += c.len_utf8() - c.len_utf16();
+                (i, chars, utf16)
+            })
+            .collect();
+        Self {
+            source,
+            line_starts,
+            multi_byte,
+        }
+    }
+
+    // Clamps an offset into the source and moves it back to the start of the
+    // character it falls within.
+    fn floor(&self, offset: usize) -> usize {
+        let mut offset = offset.min(self.source.len());
+        while !self.source.is_char_boundary(offset) {
+            offset -= 1;
+        }
+        offset
+    }
+
+    // Returns the 0-based index of the line containing an offset.
+    fn line_index(&self, offset: usize) -> usize {
+        self.line_starts.partition_point(|&start| start <= offset) - 1
+    }
+
+    // Returns the excess bytes, as picked out of `multi_byte` by `excess`, of
+    // the characters before an offset.
+    fn excess(&self, offset: usize, excess: impl Fn(&(usize, usize, usize)) -> usize) -> usize {
+        let i = self
+            .multi_byte
+            .partition_point(|&(start, _, _)| start < offset);
+        i.checked_sub(1).map_or(0, |i| excess(&self.multi_byte[i]))
+    }
+
+    fn position_with(
+        &self,
+        offset: usize,
+        excess: impl Fn(&(usize, usize, usize)) -> usize,
+    ) -> Position {
+        let offset = self.floor(offset);
+        let line = self.line_index(offset);
+        let start = self.line_starts[line];
+        let column = offset - start - (self.excess(offset, &excess) - self.excess(start, &excess));
+        Position {
+            line: line + 1,
+            column: column + 1,
+        }
+    }
+
+    /// Returns the position of a byte offset, counting columns in characters.
+    /// Offsets at or past the end of the source map to just after its last
+    /// character.
+    pub fn position(&self, offset: usize) -> Position {
+        self.position_with(offset, |&(_, chars, _)| chars)
+    }
+
+    /// Returns the position of a byte offset, counting columns in UTF-16 code
+    /// units as editors implementing the language server protocol expect.
+    pub fn utf16_position(&self, offset: usize) -> Position {
+        self.position_with(offset, |&(_, _, utf16)| utf16)
+    }
+
+    /// Returns the text of a 1-based line, without its line ending.
+    pub fn line(&self, line: usize) -> &'source str {
+        let start = self.line_starts[line - 1];
+        let end = self
+            .line_starts
+            .get(line)
+            .map_or(self.source.len(), |&next| next - 1);
+        self.source[start..end].trim_end_matches('\r')
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_position() {
+        let map = SourceMap::new("ab\ncd\n\nef");
+        let positions: Vec<_> = (0..=9).map(|i| map.position(i).to_string()).collect();
+        assert_eq!(
+            positions,
+            ["1:1", "1:2", "1:3", "2:1", "2:2", "2:3", "3:1", "4:1", "4:2", "4:3"]
+        );
+        // Past the end of the file
+        assert_eq!(map.position(20), Position { line: 4, column: 3 });
+        assert_eq!(map.line(2), "cd");
+        assert_eq!(map.line(3), "");
+        assert_eq!(map.line(4), "ef");
+    }
+
+    #[test]
+    fn test_multi_byte() {
+        // 'é' is two bytes and one UTF-16 unit, '𝄞' is four bytes and two
+        let map = SourceMap::new("é𝄞x\r\ny");
+        assert_eq!(map.position(2), Position { line: 1, column: 2 });
+        assert_eq!(map.position(6), Position { line: 1, column: 3 });
+        assert_eq!(map.utf16_position(6), Position { line: 1, column: 4 });
+        // Offsets inside a character refer to the character
+        assert_eq!(map.position(4), Position { line: 1, column: 2 });
+        assert_eq!(map.position(9), Position { line: 2, column: 1 });
+        assert_eq!(map.line(1), "é𝄞x");
+        // Characters on earlier lines don't affect later columns
+        let map = SourceMap::new("ü\nab𝄞é x");
+        assert_eq!(map.position(12), Position { line: 2, column: 6 });
+        assert_eq!(map.utf16_position(12), Position { line: 2, column: 7 });
+    }
+}
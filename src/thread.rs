@@ -1,6 +1,7 @@
-use crate::{function::Function, value::Value};
+use crate::{error::ErrorKind, function::Function, value::Value};
 
 pub struct Frame {
+    pub entry: usize,
     pub addr: usize,
     pub stack_offset: usize,
 }
@@ -15,6 +16,7 @@ impl Thread {
         Self {
             stack: vec![],
             frames: vec![Frame {
+                entry: start,
                 addr: start,
                 stack_offset: 0,
             }],
@@ -22,9 +24,7 @@ impl Thread {
     }
 
     pub fn advance(&mut self) -> Option<usize> {
-        let Some(frame) = self.frames.last_mut() else {
-            return None;
-        };
+        let frame = self.frames.last_mut()?;
         let addr = frame.addr;
         frame.addr += 1;
         Some(addr)
@@ -34,37 +34,63 @@ impl Thread {
         self.frames.is_empty()
     }
 
-    pub fn get(&self, i: usize) -> Value {
-        let stack_offset = self.frames.last().unwrap().stack_offset;
-        self.stack[stack_offset + i]
+    // The stack index of the current frame's first slot. Values beneath it
+    // belong to the caller.
+    fn base(&self) -> usize {
+        self.frames.last().map_or(0, |frame| frame.stack_offset)
     }
 
-    pub fn pop(&mut self) -> Value {
-        self.stack.pop().unwrap()
+    pub fn get(&self, i: usize) -> Result<Value, ErrorKind> {
+        self.stack
+            .get(self.base() + i)
+            .copied()
+            .ok_or(ErrorKind::StackUnderflow)
     }
 
-    pub fn peek(&self) -> Value {
-        *self.stack.last().unwrap()
+    pub fn set(&mut self, i: usize, value: Value) -> Result<(), ErrorKind> {
+        let i = self.base() + i;
+        let slot = self.stack.get_mut(i).ok_or(ErrorKind::StackUnderflow)?;
+        *slot = value;
+        Ok(())
     }
 
-    pub fn pop_n(&mut self, n: usize) -> Vec<Value> {
-        self.stack.split_off(self.stack.len() - n)
+    pub fn pop(&mut self) -> Result<Value, ErrorKind> {
+        if self.stack.len() <= self.base() {
+            return Err(ErrorKind::StackUnderflow);
+        }
+        Ok(self.stack.pop().unwrap())
+    }
+
+    pub fn peek(&self) -> Result<Value, ErrorKind> {
+        if self.stack.len() <= self.base() {
+            return Err(ErrorKind::StackUnderflow);
+        }
+        Ok(*self.stack.last().unwrap())
+    }
+
+    pub fn pop_n(&mut self, n: usize) -> Result<Vec<Value>, ErrorKind> {
+        if self.stack.len() < self.base() + n {
+            return Err(ErrorKind::StackUnderflow);
+        }
+        Ok(self.stack.split_off(self.stack.len() - n))
     }
 
     pub fn push(&mut self, value: Value) {
         self.stack.push(value)
     }
 
-    pub fn ret(&mut self) {
+    pub fn ret(&mut self) -> Result<(), ErrorKind> {
+        let retval = self.pop()?;
         let frame = self.frames.pop().unwrap();
-        let retval = self.pop();
         self.stack.truncate(frame.stack_offset);
         self.push(retval);
+        Ok(())
     }
 
-    pub fn call(&mut self, function: Function) {
+    pub fn call(&mut self, function: Function) -> Result<(), ErrorKind> {
         // Stack frame is laid out as follows (assuming n arguments and m
-        // enclosed objects):
+        // enclosed objects), with the function's local bindings and
+        // temporaries pushed above:
         //
         //   n+1+m - closure m
         //   ...
@@ -73,12 +99,35 @@ impl Thread {
         //   n-1   - arg n
         //   ...
         //   0     - arg 0
-        let stack_size = function.num_params as usize + 1 + function.closure.len();
+        let num_values = function.num_params as usize + 1;
+        if self.stack.len() < self.base() + num_values {
+            return Err(ErrorKind::StackUnderflow);
+        }
+        let stack_size = num_values + function.closure.len();
         self.stack.extend(function.closure);
         let frame = Frame {
+            entry: function.entry,
             addr: function.entry,
             stack_offset: self.stack.len() - stack_size,
         };
         self.frames.push(frame);
+        Ok(())
+    }
+
+    // Calls a function in place of the current one, reusing its frame. The
+    // arguments and function object on top of the stack are moved down to the
+    // start of the frame, and everything else in the frame is dropped.
+    pub fn tail_call(&mut self, function: Function) -> Result<(), ErrorKind> {
+        let num_values = function.num_params as usize + 1;
+        let base = self.base();
+        if self.stack.len() < base + num_values {
+            return Err(ErrorKind::StackUnderflow);
+        }
+        self.stack.drain(base..self.stack.len() - num_values);
+        self.stack.extend(function.closure);
+        let frame = self.frames.last_mut().unwrap();
+        frame.entry = function.entry;
+        frame.addr = function.entry;
+        Ok(())
     }
 }
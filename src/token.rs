@@ -2,7 +2,22 @@ use std::{fmt::Display, iter::from_fn, ops::Range};
 
 use itertools::Itertools;
 
-struct SourceLocation(Range<usize>);
+use crate::diagnostic::Diagnostic;
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct SourceLocation(pub Range<usize>);
+
+impl SourceLocation {
+    /// Returns a location spanning from the start of `self` to the end of
+    /// `other`.
+    pub fn to(&self, other: &SourceLocation) -> SourceLocation {
+        SourceLocation(self.0.start..other.0.end)
+    }
+
+    fn shift(&mut self, offset: usize) {
+        self.0 = self.0.start + offset..self.0.end + offset;
+    }
+}
 
 trait TokenString {
     fn consume<'a, T>(self, options: impl IntoIterator<Item = (&'a str, T)>) -> Option<(usize, T)>;
@@ -27,71 +42,415 @@ impl TokenString for &str {
     }
 }
 
-fn locate(source: &str, i: usize) -> String {
-    let mut line_no = 0;
-    let mut col_no = 0;
-    for (j, c) in source.char_indices() {
-        if j == i {
-            return format!("{line_no}:{col_no}");
-        }
-        col_no += 1;
-        if c == '\n' {
-            line_no += 1;
-            col_no = 0;
-        }
-    }
-    panic!("invalid source offset {i}");
-}
-
-enum Kind {
+#[derive(Clone, Debug, PartialEq)]
+pub enum Kind {
+    AmpAmp,
+    Bang,
+    BangEqual,
     BraceClose,
     BraceOpen,
+    BracketClose,
+    BracketOpen,
+    Break,
+    Colon,
+    Comma,
+    Comment(String),
+    Continue,
+    Dot,
+    DotDot,
+    Else,
     Equal,
+    EqualEqual,
+    False,
     FatArrow,
+    Float(f64),
+    For,
+    Greater,
+    GreaterEqual,
+    If,
+    In,
     Integer(i64),
+    // A loop label, as in `'outer`
+    Label(String),
+    Less,
+    LessEqual,
+    Let,
+    Match,
+    Minus,
     Name(String),
     Newline,
     ParenClose,
     ParenOpen,
+    Percent,
+    Pipe,
+    PipePipe,
     Plus,
+    Record,
+    Slash,
+    Star,
+    StarStar,
+    String(String),
+    Template(Vec<TemplatePart>),
+    True,
+    While,
+    With,
 }
 
-struct Token {
-    kind: Kind,
-    location: SourceLocation,
+impl Kind {
+    // Whether the token needs something after it to complete the expression
+    // or statement it's part of, such as a binary operator.
+    fn expects_more(&self) -> bool {
+        matches!(
+            self,
+            Kind::AmpAmp
+                | Kind::Bang
+                | Kind::BangEqual
+                | Kind::Colon
+                | Kind::Comma
+                | Kind::Dot
+                | Kind::DotDot
+                | Kind::Equal
+                | Kind::EqualEqual
+                | Kind::FatArrow
+                | Kind::Greater
+                | Kind::GreaterEqual
+                | Kind::In
+                | Kind::Less
+                | Kind::LessEqual
+                | Kind::Minus
+                | Kind::Percent
+                | Kind::Pipe
+                | Kind::PipePipe
+                | Kind::Plus
+                | Kind::Slash
+                | Kind::Star
+                | Kind::StarStar
+        )
+    }
+}
+
+/// A piece of a string literal containing interpolations, such as
+/// `"total: {a + b}"`.
+#[derive(Clone, Debug, PartialEq)]
+pub enum TemplatePart {
+    Text(String),
+    /// The tokens of an interpolated expression, and the location of the
+    /// source between its braces.
+    Code(Vec<Token>, SourceLocation),
 }
 
-fn tokenise(source: &str) -> impl Iterator<Item = Result<Token, String>> + '_ {
-    type KindResult = (Result<Option<Kind>, String>, usize);
+#[derive(Clone, Debug, PartialEq)]
+pub struct Token {
+    pub kind: Kind,
+    pub location: SourceLocation,
+}
+
+impl Token {
+    // Moves a token lexed from part of a source so its location is relative to
+    // the whole source.
+    fn shift(&mut self, offset: usize) {
+        self.location.shift(offset);
+        if let Kind::Template(parts) = &mut self.kind {
+            for part in parts {
+                if let TemplatePart::Code(tokens, location) = part {
+                    tokens.iter_mut().for_each(|token| token.shift(offset));
+                    location.shift(offset);
+                }
+            }
+        }
+    }
+}
+
+fn tokenise(source: &str) -> impl Iterator<Item = Result<Token, Diagnostic>> + '_ {
+    // The kind of the next token and the number of bytes it spans. Errors carry
+    // the span to report relative to the start of the token, which may be
+    // narrower than what was consumed.
+    type KindResult = (Result<Option<Kind>, (String, Range<usize>)>, usize);
     fn find_offset(s: &str, mut f: impl FnMut(char) -> bool) -> usize {
         s.char_indices()
             .find(move |(_, c)| f(*c))
             .map(|(i, _)| i)
             .unwrap_or(s.len())
     }
-    fn next_integer(s: &str) -> KindResult {
-        let len = find_offset(s, |c| !c.is_ascii_digit());
-        let s = &s[..len];
-        let kind = s
-            .parse()
-            .map(Kind::Integer)
-            .map(Some)
-            .map_err(|_| format!("integer token too large {}", s));
+    fn next_number(s: &str) -> KindResult {
+        // Digits may be separated by underscores, as in `1_000`
+        let digits = |s: &str| find_offset(s, |c| !c.is_ascii_digit() && c != '_');
+        let starts_with_digit = |s: &str| s.starts_with(|c: char| c.is_ascii_digit());
+        let mut len = digits(s);
+        let mut float = false;
+        if s[len..].starts_with('.') && starts_with_digit(&s[len + 1..]) {
+            len += 1 + digits(&s[len + 1..]);
+            float = true;
+        }
+        if s[len..].starts_with(['e', 'E']) {
+            let sign = s[len + 1..].starts_with(['+', '-']) as usize;
+            let e = len + 1 + sign;
+            if starts_with_digit(&s[e..]) {
+                len = e + digits(&s[e..]);
+                float = true;
+            }
+        }
+        let number = s[..len].replace('_', "");
+        let kind = if float {
+            // Parsing only fails on malformed input, and out of range values
+            // saturate to infinity
+            Ok(Some(Kind::Float(number.parse().unwrap())))
+        } else {
+            number
+                .parse()
+                .map(Kind::Integer)
+                .map(Some)
+                .map_err(|_| (format!("integer token too large {}", &s[..len]), 0..len))
+        };
         (kind, len)
     }
     fn next_name(s: &str) -> KindResult {
         let len = find_offset(s, |c| !c.is_alphanumeric() && c != '_');
-        (Ok(Some(Kind::Name(s[..len].into()))), len)
+        let kind = match &s[..len] {
+            "break" => Kind::Break,
+            "continue" => Kind::Continue,
+            "else" => Kind::Else,
+            "false" => Kind::False,
+            "for" => Kind::For,
+            "if" => Kind::If,
+            "in" => Kind::In,
+            "let" => Kind::Let,
+            "match" => Kind::Match,
+            "record" => Kind::Record,
+            "true" => Kind::True,
+            "while" => Kind::While,
+            "with" => Kind::With,
+            name => Kind::Name(name.into()),
+        };
+        (Ok(Some(kind)), len)
+    }
+    fn next_label(s: &str) -> KindResult {
+        let len = 1 + find_offset(&s[1..], |c| !c.is_alphanumeric() && c != '_');
+        if !s[1..].starts_with(|c: char| c.is_alphabetic() || c == '_') {
+            return (Err(("expected a name for the label".into(), 0..1)), 1);
+        }
+        (Ok(Some(Kind::Label(s[1..len].into()))), len)
+    }
+    fn next_line_comment(s: &str) -> KindResult {
+        let mut len = find_offset(s, |c| c == '\n');
+        // The line ending is lexed separately, whether it's CRLF or not
+        if s[len..].starts_with('\n') && s[..len].ends_with('\r') {
+            len -= 1;
+        }
+        (Ok(Some(Kind::Comment(s[..len].into()))), len)
+    }
+    fn next_block_comment(s: &str) -> KindResult {
+        // Block comments nest, so every opening needs its own closing
+        let mut depth = 0;
+        let mut len = 0;
+        while len < s.len() {
+            if s[len..].starts_with("/*") {
+                depth += 1;
+                len += 2;
+            } else if s[len..].starts_with("*/") {
+                depth -= 1;
+                len += 2;
+                if depth == 0 {
+                    return (Ok(Some(Kind::Comment(s[..len].into()))), len);
+                }
+            } else {
+                len += s[len..].chars().next().unwrap().len_utf8();
+            }
+        }
+        (Err(("unterminated block comment".into(), 0..2)), len)
+    }
+    fn next_whitespace(s: &str) -> KindResult {
+        let len = find_offset(s, |c| !c.is_whitespace() || c == '\n' || c == '\r');
+        (Ok(None), len)
+    }
+    fn next_continuation(s: &str) -> KindResult {
+        // A backslash followed by the end of the line joins it to the next
+        let len = 1 + find_offset(&s[1..], |c| !c.is_whitespace() || c == '\n' || c == '\r');
+        if let Some((newline, _)) = s[len..].consume([("\r\n", ()), ("\n", ())]) {
+            return (Ok(None), len + newline);
+        }
+        (
+            Err(("expected the end of the line after '\\'".into(), 0..1)),
+            1,
+        )
+    }
+    // Returns the character an escape sequence at the start of `s` stands for
+    // and its length, or the length of the invalid sequence.
+    fn next_escape(s: &str) -> Result<(char, usize), usize> {
+        let c = match s[1..].chars().next() {
+            Some('n') => '\n',
+            Some('t') => '\t',
+            Some('r') => '\r',
+            Some('0') => '\0',
+            Some('\\') => '\\',
+            Some('"') => '"',
+            Some('{') => '{',
+            Some('}') => '}',
+            Some('u') if s[2..].starts_with('{') => {
+                let digits = find_offset(&s[3..], |c| !c.is_ascii_hexdigit());
+                let len = 3 + digits;
+                if !s[len..].starts_with('}') || digits > 6 {
+                    return Err(len);
+                }
+                return u32::from_str_radix(&s[3..len], 16)
+                    .ok()
+                    .and_then(char::from_u32)
+                    .map(|c| (c, len + 1))
+                    .ok_or(len + 1);
+            }
+            Some(c) if c != '\n' => return Err(1 + c.len_utf8()),
+            _ => return Err(1),
+        };
+        Ok((c, 2))
+    }
+    // Lexes the expression in an interpolation at the start of `s`, returning
+    // its tokens relative to `s` and the length of the interpolation including
+    // its braces.
+    fn next_interpolation(s: &str) -> Result<(Vec<Token>, usize), (String, Range<usize>)> {
+        let mut depth = 0;
+        let mut len = 0;
+        loop {
+            match s[len..].chars().next() {
+                Some('{') => depth += 1,
+                Some('}') => depth -= 1,
+                // Strings inside the expression may contain braces of their own
+                Some('"') => {
+                    len += next_string(&s[len..]).1;
+                    continue;
+                }
+                Some('\n') | None => return Err(("unterminated interpolation".into(), 0..1)),
+                Some(_) => {}
+            }
+            len += s[len..].chars().next().unwrap().len_utf8();
+            if depth == 0 {
+                break;
+            }
+        }
+        let mut tokens = Vec::new();
+        for token in tokenise(&s[1..len - 1]) {
+            match token {
+                Ok(Token {
+                    kind: Kind::Comment(_),
+                    ..
+                }) => {}
+                Ok(mut token) => {
+                    token.shift(1);
+                    tokens.push(token);
+                }
+                Err(diagnostic) => {
+                    let span = diagnostic.location.unwrap().0;
+                    return Err((diagnostic.message, span.start + 1..span.end + 1));
+                }
+            }
+        }
+        Ok((tokens, len))
+    }
+    fn next_string(s: &str) -> KindResult {
+        // Strings can't span lines. An invalid escape doesn't end the string,
+        // so the rest of it is skipped rather than lexed as code.
+        let mut parts = Vec::new();
+        let mut value = String::new();
+        let mut error = None;
+        let mut len = 1;
+        while let Some(c) = s[len..].chars().next() {
+            match c {
+                '"' => {
+                    let kind = match error {
+                        Some(error) => Err(error),
+                        None if parts.is_empty() => Ok(Some(Kind::String(value))),
+                        None => {
+                            if !value.is_empty() {
+                                parts.push(TemplatePart::Text(value));
+                            }
+                            Ok(Some(Kind::Template(parts)))
+                        }
+                    };
+                    return (kind, len + 1);
+                }
+                '\n' => break,
+                '{' => match next_interpolation(&s[len..]) {
+                    Ok((mut tokens, interpolation_len)) => {
+                        if !value.is_empty() {
+                            parts.push(TemplatePart::Text(std::mem::take(&mut value)));
+                        }
+                        tokens.iter_mut().for_each(|token| token.shift(len));
+                        let code = SourceLocation(len + 1..len + interpolation_len - 1);
+                        parts.push(TemplatePart::Code(tokens, code));
+                        len += interpolation_len;
+                    }
+                    Err((message, span)) => {
+                        let span = span.start + len..span.end + len;
+                        return (Err((message, span)), find_offset(s, |c| c == '\n'));
+                    }
+                },
+                '\\' => match next_escape(&s[len..]) {
+                    Ok((c, escape_len)) => {
+                        value.push(c);
+                        len += escape_len;
+                    }
+                    Err(escape_len) => {
+                        let escape = &s[len..len + escape_len];
+                        error.get_or_insert((
+                            format!("invalid escape sequence '{escape}'"),
+                            len..len + escape_len,
+                        ));
+                        len += escape_len;
+                    }
+                },
+                c => {
+                    value.push(c);
+                    len += c.len_utf8();
+                }
+            }
+        }
+        (Err(("unterminated string".into(), 0..1)), len)
     }
     fn next_token(s: &str) -> KindResult {
+        if s.starts_with('#') || s.starts_with("//") {
+            return next_line_comment(s);
+        }
+        if s.starts_with("/*") {
+            return next_block_comment(s);
+        }
+        if s.starts_with('\\') {
+            return next_continuation(s);
+        }
+        if s.starts_with('"') {
+            return next_string(s);
+        }
+        if s.starts_with('\'') {
+            return next_label(s);
+        }
         if let Some((length, maybe_kind)) = s.consume([
-            (" ", None),
+            ("\r\n", Some(Kind::Newline)),
+            ("\r", None),
             ("(", Some(Kind::ParenOpen)),
             (")", Some(Kind::ParenClose)),
             ("{", Some(Kind::BraceOpen)),
             ("}", Some(Kind::BraceClose)),
+            ("[", Some(Kind::BracketOpen)),
+            ("]", Some(Kind::BracketClose)),
+            (",", Some(Kind::Comma)),
+            (":", Some(Kind::Colon)),
+            ("..", Some(Kind::DotDot)),
+            (".", Some(Kind::Dot)),
             ("\n", Some(Kind::Newline)),
             ("+", Some(Kind::Plus)),
+            ("-", Some(Kind::Minus)),
+            ("**", Some(Kind::StarStar)),
+            ("*", Some(Kind::Star)),
+            ("/", Some(Kind::Slash)),
+            ("%", Some(Kind::Percent)),
+            ("==", Some(Kind::EqualEqual)),
+            ("!=", Some(Kind::BangEqual)),
+            ("<=", Some(Kind::LessEqual)),
+            ("<", Some(Kind::Less)),
+            (">=", Some(Kind::GreaterEqual)),
+            (">", Some(Kind::Greater)),
+            ("&&", Some(Kind::AmpAmp)),
+            ("||", Some(Kind::PipePipe)),
+            ("|", Some(Kind::Pipe)),
+            ("!", Some(Kind::Bang)),
             ("=>", Some(Kind::FatArrow)),
             ("=", Some(Kind::Equal)),
         ]) {
@@ -99,9 +458,10 @@ fn tokenise(source: &str) -> impl Iterator<Item = Result<Token, String>> + '_ {
         }
         let (j, c) = s.char_lengths().next().unwrap();
         match c {
-            '0'..='9' => next_integer(s),
-            c if c.is_alphabetic() => next_name(s),
-            c => (Err(format!("unexpected token '{c}'")), j),
+            c if c.is_whitespace() => next_whitespace(s),
+            '0'..='9' => next_number(s),
+            c if c.is_alphabetic() || c == '_' => next_name(s),
+            c => (Err((format!("unexpected token '{c}'"), 0..j)), j),
         }
     }
     let mut next_i = 0;
@@ -114,10 +474,17 @@ fn tokenise(source: &str) -> impl Iterator<Item = Result<Token, String>> + '_ {
         let prev_i = next_i;
         next_i += length;
         let result = match result {
-            Err(msg) => Err(format!("{msg} at {}", locate(source, prev_i))),
+            Err((msg, span)) => Err(Diagnostic::error(
+                msg,
+                SourceLocation(prev_i + span.start..prev_i + span.end),
+            )),
             Ok(Some(kind)) => {
-                let location = SourceLocation(prev_i..next_i);
-                Ok(Token { kind, location })
+                let mut token = Token {
+                    kind,
+                    location: SourceLocation(0..length),
+                };
+                token.shift(prev_i);
+                Ok(token)
             }
             Ok(None) => continue,
         };
@@ -126,24 +493,134 @@ fn tokenise(source: &str) -> impl Iterator<Item = Result<Token, String>> + '_ {
 }
 
 pub struct Tokens<'source> {
-    source: &'source str,
-    tokens: Vec<Token>,
+    pub source: &'source str,
+    pub tokens: Vec<Token>,
 }
 
 impl<'source> Tokens<'source> {
-    pub fn from_source(source: &'source str) -> Result<Self, String> {
+    pub fn from_source(source: &'source str) -> Result<Self, Vec<Diagnostic>> {
+        Self::lex(source, false)
+    }
+
+    /// Like `from_source`, but keeps comments as `Kind::Comment` tokens so that
+    /// tools like formatters can reproduce them. The parser skips them.
+    pub fn with_comments(source: &'source str) -> Result<Self, Vec<Diagnostic>> {
+        Self::lex(source, true)
+    }
+
+    fn lex(source: &'source str, comments: bool) -> Result<Self, Vec<Diagnostic>> {
         let mut errors = Vec::new();
         let mut tokens = Vec::new();
+        // Newlines only separate statements, so they're insignificant inside
+        // parentheses or brackets unless there's a block between them and the
+        // newline. Elsewhere, including in blocks, a newline continues the
+        // line if the token before it can't end a statement.
+        let mut brackets = Vec::new();
+        let mut last: Option<Kind> = None;
         for token in tokenise(source) {
+            if let Ok(token) = &token {
+                match token.kind {
+                    Kind::ParenOpen | Kind::BraceOpen | Kind::BracketOpen => {
+                        brackets.push(token.kind.clone())
+                    }
+                    Kind::ParenClose | Kind::BraceClose | Kind::BracketClose => {
+                        brackets.pop();
+                    }
+                    _ => {}
+                }
+            }
             match token {
-                Ok(token) => tokens.push(token),
-                Err(msg) => errors.push(msg),
+                Ok(Token {
+                    kind: Kind::Comment(_),
+                    ..
+                }) if !comments => {}
+                Ok(Token {
+                    kind: Kind::Newline,
+                    ..
+                }) if matches!(brackets.last(), Some(Kind::ParenOpen | Kind::BracketOpen))
+                    || last.as_ref().is_some_and(Kind::expects_more) => {}
+                Ok(token) => {
+                    if !matches!(token.kind, Kind::Comment(_)) {
+                        last = Some(token.kind.clone());
+                    }
+                    tokens.push(token);
+                }
+                Err(diagnostic) => errors.push(diagnostic),
             }
         }
         if errors.is_empty() {
             Ok(Self { source, tokens })
         } else {
-            Err(errors.join("\n"))
+            Err(errors)
+        }
+    }
+}
+
+impl Display for Kind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Kind::AmpAmp => write!(f, "<AmpAmp>"),
+            Kind::Bang => write!(f, "<Bang>"),
+            Kind::BangEqual => write!(f, "<BangEqual>"),
+            Kind::BraceClose => write!(f, "<BraceClose>"),
+            Kind::BraceOpen => write!(f, "<BraceOpen>"),
+            Kind::BracketClose => write!(f, "<BracketClose>"),
+            Kind::BracketOpen => write!(f, "<BracketOpen>"),
+            Kind::Break => write!(f, "<Break>"),
+            Kind::Colon => write!(f, "<Colon>"),
+            Kind::Comma => write!(f, "<Comma>"),
+            Kind::Comment(comment) => write!(f, "<Comment {comment}>"),
+            Kind::Continue => write!(f, "<Continue>"),
+            Kind::Dot => write!(f, "<Dot>"),
+            Kind::DotDot => write!(f, "<DotDot>"),
+            Kind::Else => write!(f, "<Else>"),
+            Kind::Equal => write!(f, "<Equal>"),
+            Kind::EqualEqual => write!(f, "<EqualEqual>"),
+            Kind::False => write!(f, "<False>"),
+            Kind::FatArrow => write!(f, "<FatArrow>"),
+            Kind::Float(float) => write!(f, "<Float {float:?}>"),
+            Kind::For => write!(f, "<For>"),
+            Kind::Greater => write!(f, "<Greater>"),
+            Kind::GreaterEqual => write!(f, "<GreaterEqual>"),
+            Kind::If => write!(f, "<If>"),
+            Kind::In => write!(f, "<In>"),
+            Kind::Integer(int) => write!(f, "<Integer {int}>"),
+            Kind::Label(label) => write!(f, "<Label {label}>"),
+            Kind::Less => write!(f, "<Less>"),
+            Kind::LessEqual => write!(f, "<LessEqual>"),
+            Kind::Let => write!(f, "<Let>"),
+            Kind::Match => write!(f, "<Match>"),
+            Kind::Minus => write!(f, "<Minus>"),
+            Kind::Name(name) => write!(f, "<Name {name}>"),
+            Kind::Newline => write!(f, "<Newline>"),
+            Kind::ParenClose => write!(f, "<ParenClose>"),
+            Kind::ParenOpen => write!(f, "<ParenOpen>"),
+            Kind::Percent => write!(f, "<Percent>"),
+            Kind::Pipe => write!(f, "<Pipe>"),
+            Kind::PipePipe => write!(f, "<PipePipe>"),
+            Kind::Plus => write!(f, "<Plus>"),
+            Kind::Record => write!(f, "<Record>"),
+            Kind::Slash => write!(f, "<Slash>"),
+            Kind::Star => write!(f, "<Star>"),
+            Kind::StarStar => write!(f, "<StarStar>"),
+            Kind::String(string) => write!(f, "<String {string:?}>"),
+            Kind::Template(parts) => {
+                write!(f, "<Template")?;
+                for part in parts {
+                    match part {
+                        TemplatePart::Text(text) => write!(f, " {text:?}")?,
+                        TemplatePart::Code(tokens, _) => write!(
+                            f,
+                            " {{{}}}",
+                            tokens.iter().map(|token| &token.kind).join(" ")
+                        )?,
+                    }
+                }
+                write!(f, ">")
+            }
+            Kind::True => write!(f, "<True>"),
+            Kind::While => write!(f, "<While>"),
+            Kind::With => write!(f, "<With>"),
         }
     }
 }
@@ -154,19 +631,152 @@ impl Display for Tokens<'_> {
             if i != 0 {
                 write!(f, " ")?;
             }
-            match &token.kind {
-                Kind::BraceClose => write!(f, "<BraceClose>")?,
-                Kind::BraceOpen => write!(f, "<BraceOpen>")?,
-                Kind::Equal => write!(f, "<Equal>")?,
-                Kind::FatArrow => write!(f, "<FatArrow>")?,
-                Kind::Integer(int) => write!(f, "<Integer {int}>")?,
-                Kind::Name(name) => write!(f, "<Name {name}>")?,
-                Kind::Newline => write!(f, "<Newline>")?,
-                Kind::ParenClose => write!(f, "<ParenClose>")?,
-                Kind::ParenOpen => write!(f, "<ParenOpen>")?,
-                Kind::Plus => write!(f, "<Plus>")?,
-            }
+            write!(f, "{}", token.kind)?;
         }
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_comments() {
+        let source = "# one\nx = 1 // two\n/* three /* four */ */ x + /* five */ x\n";
+        assert_eq!(
+            Tokens::from_source(source).unwrap().to_string(),
+            "<Newline> <Name x> <Equal> <Integer 1> <Newline> \
+             <Name x> <Plus> <Name x> <Newline>"
+        );
+        assert_eq!(
+            Tokens::with_comments(source).unwrap().to_string(),
+            "<Comment # one> <Newline> <Name x> <Equal> <Integer 1> <Comment // two> <Newline> \
+             <Comment /* three /* four */ */> <Name x> <Plus> <Comment /* five */> <Name x> <Newline>"
+        );
+        // Line comments don't include the carriage return of a CRLF ending
+        assert_eq!(
+            Tokens::with_comments("# hi\r\nx = 1 // c\r\n")
+                .unwrap()
+                .to_string(),
+            "<Comment # hi> <Newline> <Name x> <Equal> <Integer 1> <Comment // c> <Newline>"
+        );
+    }
+
+    #[test]
+    fn test_whitespace() {
+        let source = "x\t=\u{a0}1\r\ny = f(1,\r\n  2) + \\ \n  g(() => {\n x\n })\n";
+        assert_eq!(
+            Tokens::from_source(source).unwrap().to_string(),
+            "<Name x> <Equal> <Integer 1> <Newline> \
+             <Name y> <Equal> <Name f> <ParenOpen> <Integer 1> <Comma> <Integer 2> <ParenClose> \
+             <Plus> <Name g> <ParenOpen> <ParenOpen> <ParenClose> <FatArrow> <BraceOpen> \
+             <Newline> <Name x> <Newline> <BraceClose> <ParenClose> <Newline>"
+        );
+        // In a block, a newline after an operator continues the expression
+        // while other newlines still separate statements
+        let source = "f = () => {\n  x = 1 +\n    2\n  x *\n  x\n}\n";
+        assert_eq!(
+            Tokens::from_source(source).unwrap().to_string(),
+            "<Name f> <Equal> <ParenOpen> <ParenClose> <FatArrow> <BraceOpen> <Newline> \
+             <Name x> <Equal> <Integer 1> <Plus> <Integer 2> <Newline> \
+             <Name x> <Star> <Name x> <Newline> <BraceClose> <Newline>"
+        );
+        assert_eq!(
+            Tokens::from_source("x = 1 \\ 2").err(),
+            Some(vec![Diagnostic::error(
+                "expected the end of the line after '\\'",
+                SourceLocation(6..7)
+            )])
+        );
+    }
+
+    #[test]
+    fn test_numbers() {
+        let source = "1_000 1.5 2e3 2.5E-1 1_0.0_1 1e";
+        assert_eq!(
+            Tokens::from_source(source).unwrap().to_string(),
+            "<Integer 1000> <Float 1.5> <Float 2000.0> <Float 0.25> <Float 10.01> \
+             <Integer 1> <Name e>"
+        );
+        assert_eq!(
+            Tokens::from_source("x = 9_223_372_036_854_775_808").err(),
+            Some(vec![Diagnostic::error(
+                "integer token too large 9_223_372_036_854_775_808",
+                SourceLocation(4..29)
+            )])
+        );
+    }
+
+    #[test]
+    fn test_strings() {
+        let source = r#"s = "a\tb\n\"c\" \\ \u{1F600}é" + """#;
+        assert_eq!(
+            Tokens::from_source(source).unwrap().to_string(),
+            r#"<Name s> <Equal> <String "a\tb\n\"c\" \\ 😀é"> <Plus> <String "">"#
+        );
+        assert_eq!(
+            Tokens::from_source("x = \"abc\ny = \"\\q \\u{110000}\"").err(),
+            Some(vec![
+                Diagnostic::error("unterminated string", SourceLocation(4..5)),
+                Diagnostic::error("invalid escape sequence '\\q'", SourceLocation(14..16)),
+            ])
+        );
+    }
+
+    #[test]
+    fn test_templates() {
+        let tokens = Tokens::from_source("x = \"a{b}\"").unwrap();
+        let Kind::Template(parts) = &tokens.tokens[2].kind else {
+            panic!("expected a template");
+        };
+        assert_eq!(
+            parts,
+            &[
+                TemplatePart::Text("a".into()),
+                TemplatePart::Code(
+                    vec![Token {
+                        kind: Kind::Name("b".into()),
+                        location: SourceLocation(7..8)
+                    }],
+                    SourceLocation(7..8)
+                )
+            ]
+        );
+        assert_eq!(
+            Tokens::from_source("x = \"{1 ☃}\"\ny = \"{a\"\n").err(),
+            Some(vec![
+                Diagnostic::error("unexpected token '☃'", SourceLocation(8..11)),
+                Diagnostic::error("unterminated interpolation", SourceLocation(19..20)),
+            ])
+        );
+    }
+
+    #[test]
+    fn test_labels() {
+        let tokens = Tokens::from_source("'outer: break 'outer").unwrap();
+        assert_eq!(
+            tokens.tokens.iter().map(|t| t.kind.to_string()).join(" "),
+            "<Label outer> <Colon> <Break> <Label outer>"
+        );
+        assert_eq!(
+            Tokens::from_source("break '1").err(),
+            Some(vec![Diagnostic::error(
+                "expected a name for the label",
+                SourceLocation(6..7)
+            )])
+        );
+    }
+
+    #[test]
+    fn test_unterminated_comment() {
+        let source = "x = 1\n/* one /* two */\nx";
+        assert_eq!(
+            Tokens::from_source(source).err(),
+            Some(vec![Diagnostic::error(
+                "unterminated block comment",
+                SourceLocation(6..8)
+            )])
+        );
+    }
+}
@@ -1,15 +1,21 @@
+use crate::builtin::Builtin;
+
 #[derive(Clone, Copy)]
 pub enum Value {
     Nil,
+    Bool(bool),
     Integer(i64),
+    Float(f64),
+    Builtin(Builtin),
     Object(usize),
 }
 
 impl Value {
-    pub fn integer(&self) -> i64 {
+    #[cfg(test)]
+    pub fn integer(&self) -> Option<i64> {
         if let Value::Integer(n) = self {
-            return *n;
+            return Some(*n);
         }
-        panic!("value is not an integer");
+        None
     }
 }
@@ -1,13 +1,145 @@
-use crate::{function::Function, object::Object, pool::ObjectPool, thread::Thread, value::Value};
+use crate::{
+    builtin::Builtin,
+    error::{ErrorKind, RuntimeError, TraceFrame},
+    function::Function,
+    map::Map,
+    object::Object,
+    pool::ObjectPool,
+    record::{Record, Shape},
+    thread::Thread,
+    value::Value,
+};
 
 #[derive(Clone, Copy)]
-enum Expr {
+pub enum Expr {
+    Nil,
+    Pop,
+    // Pops the top value and `count` values beneath it, then pushes the top
+    // value back
+    PopBelow {
+        count: u32,
+    },
     Load {
         i: usize,
     },
+    // Pops a value and stores it in slot `i` of the current frame
+    Store {
+        i: usize,
+    },
+    // Moves the value in slot `i` into a new upvalue, which takes its place
+    NewUpvalue {
+        i: usize,
+    },
+    // Pushes the value held by the upvalue in slot `i`
+    LoadUpvalue {
+        i: usize,
+    },
+    // Pops a value and stores it in the upvalue in slot `i`
+    StoreUpvalue {
+        i: usize,
+    },
+    // Pushes the value of global `index`, whose name is the string constant
+    // `name`
+    LoadGlobal {
+        index: usize,
+        name: usize,
+    },
+    // Pops a value and stores it in global `index`
+    StoreGlobal {
+        index: usize,
+    },
     Literal {
         integer: i64,
     },
+    FloatLiteral {
+        float: f64,
+    },
+    BoolLiteral {
+        boolean: bool,
+    },
+    // Allocates a copy of a string from the program's string constants
+    StringLiteral {
+        index: usize,
+    },
+    Builtin {
+        builtin: Builtin,
+    },
+    // Converts the top `count` values to strings and concatenates them
+    Concat {
+        count: u32,
+    },
+    // Collects the top `len` values into a new list
+    List {
+        len: u32,
+    },
+    // Collects the top `2 * len` values, alternating keys and values, into a
+    // new map
+    Map {
+        len: u32,
+    },
+    // Allocates a record type named by the string constant `name`, with fields
+    // named by the `len` string constants starting at `fields`
+    Shape {
+        name: usize,
+        fields: usize,
+        len: u32,
+    },
+    // Pops a record type and pushes a new record of that type with every field
+    // nil, checking that the type has `len` fields. The fields are then filled
+    // in with `SetField` or `SetNamedField`.
+    NewRecord {
+        len: u32,
+    },
+    // Replaces the record on top of the stack with a copy of it
+    CopyRecord,
+    // Pops a record and pushes the field in `slot`
+    GetField {
+        slot: u32,
+    },
+    // Pops a record and pushes the field named by the string constant `name`
+    GetNamedField {
+        name: usize,
+    },
+    // Pops a value and stores it in `slot` of the record beneath it, which is
+    // left on the stack
+    SetField {
+        slot: u32,
+    },
+    // Like `SetField`, but for the field named by the string constant `name`
+    SetNamedField {
+        name: usize,
+    },
+    // Pops a union variant's constructor, or for a variant without fields its
+    // value, and another value, and pushes whether that value is a record of
+    // the variant
+    TestVariant,
+    // Pops a value and pushes whether it's a list of exactly `len` items, or
+    // at least `len` if `rest` is set
+    TestList {
+        len: u32,
+        rest: bool,
+    },
+    // Pops a list and pushes a new list of its items from `start` onwards
+    ListFrom {
+        start: u32,
+    },
+    // Pops a value and raises an error that no match arm matched it
+    NoMatch,
+    // Pops a list or map and pushes its length, for a `for` loop over it
+    IterLen,
+    // Pops a position and a list or map, and pushes the list's item or the
+    // map's key at that position
+    IterItem,
+    // Like `IterItem`, but pushes the position itself for a list
+    IterKey,
+    // Like `IterItem`, but pushes the value at that position for a map
+    IterValue,
+    // Pops an index and a list, or a key and a map, and pushes the element
+    // stored there
+    Index,
+    // Pops a value, an index and a list, or a value, a key and a map, and
+    // stores the value there. Storing to a missing key inserts it.
+    SetIndex,
     Function {
         entry: usize,
         closure_len: u32,
@@ -15,7 +147,19 @@ enum Expr {
     },
     Add,
     Sub,
-    BranchIfNotZero {
+    Mul,
+    Div,
+    Rem,
+    Pow,
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+    Neg,
+    Not,
+    BranchIfFalse {
         target: usize,
     },
     Branch {
@@ -24,11 +168,98 @@ enum Expr {
     Call {
         num_args: u32,
     },
+    // Like `Call` followed by `Return`, but the called function replaces the
+    // current one's frame rather than adding to the stack
+    TailCall {
+        num_args: u32,
+    },
     Return,
 }
 
-struct VM {
+impl Expr {
+    /// The number of values the instruction pops from the stack and the number
+    /// it then pushes, as seen by the function executing it.
+    pub fn stack_effect(self) -> (usize, usize) {
+        match self {
+            Expr::Nil
+            | Expr::Load { .. }
+            | Expr::LoadUpvalue { .. }
+            | Expr::LoadGlobal { .. }
+            | Expr::Literal { .. }
+            | Expr::FloatLiteral { .. }
+            | Expr::BoolLiteral { .. }
+            | Expr::StringLiteral { .. }
+            | Expr::Builtin { .. }
+            | Expr::Shape { .. } => (0, 1),
+            Expr::Pop
+            | Expr::Store { .. }
+            | Expr::StoreUpvalue { .. }
+            | Expr::StoreGlobal { .. }
+            | Expr::BranchIfFalse { .. }
+            | Expr::NoMatch
+            | Expr::Return => (1, 0),
+            Expr::PopBelow { count } => (count as usize + 1, 1),
+            Expr::Branch { .. } | Expr::NewUpvalue { .. } => (0, 0),
+            Expr::Concat { count } => (count as usize, 1),
+            Expr::List { len } => (len as usize, 1),
+            Expr::Map { len } => (2 * len as usize, 1),
+            Expr::NewRecord { .. }
+            | Expr::CopyRecord
+            | Expr::GetField { .. }
+            | Expr::GetNamedField { .. }
+            | Expr::TestList { .. }
+            | Expr::ListFrom { .. }
+            | Expr::IterLen
+            | Expr::Neg
+            | Expr::Not => (1, 1),
+            Expr::SetField { .. } | Expr::SetNamedField { .. } => (1, 0),
+            Expr::Index | Expr::TestVariant | Expr::IterItem | Expr::IterKey | Expr::IterValue => {
+                (2, 1)
+            }
+            Expr::SetIndex => (3, 0),
+            Expr::Function { closure_len, .. } => (closure_len as usize, 1),
+            Expr::Add
+            | Expr::Sub
+            | Expr::Mul
+            | Expr::Div
+            | Expr::Rem
+            | Expr::Pow
+            | Expr::Eq
+            | Expr::Ne
+            | Expr::Lt
+            | Expr::Le
+            | Expr::Gt
+            | Expr::Ge => (2, 1),
+            Expr::Call { num_args } | Expr::TailCall { num_args } => (num_args as usize + 1, 1),
+        }
+    }
+}
+
+// Integer arithmetic is checked, so `None` means the result didn't fit.
+fn checked(result: Option<i64>) -> Result<Value, ErrorKind> {
+    result.map(Value::Integer).ok_or(ErrorKind::Overflow)
+}
+
+// Checks that `index` refers to an element of a list of length `len`.
+fn index(index: i64, len: usize) -> Result<usize, ErrorKind> {
+    if index < 0 || index as usize >= len {
+        return Err(ErrorKind::IndexOutOfRange { index, len });
+    }
+    Ok(index as usize)
+}
+
+fn nonzero(divisor: i64) -> Result<(), ErrorKind> {
+    if divisor == 0 {
+        return Err(ErrorKind::DivisionByZero);
+    }
+    Ok(())
+}
+
+pub struct VM {
     pool: ObjectPool,
+    // The values of the program's top-level bindings, by index. A global is
+    // `None` until its definition has run.
+    globals: Vec<Option<Value>>,
     pub debug: bool,
 }
 
@@ -36,30 +267,504 @@ impl VM {
     pub fn new() -> Self {
         Self {
             pool: ObjectPool::new(),
+            globals: Vec::new(),
             debug: false,
         }
     }
 
-    fn step(&mut self, expr: Expr, thread: Value) -> Option<Value> {
+    pub fn to_string(&self, value: &Value) -> String {
+        self.pool.to_string(value)
+    }
+
+    // Conditions must be booleans; there's no implicit truthiness.
+    fn bool(&self, value: Value) -> Result<bool, ErrorKind> {
+        match value {
+            Value::Bool(b) => Ok(b),
+            _ => Err(ErrorKind::TypeMismatch {
+                expected: "bool",
+                found: self.pool.type_name(&value),
+            }),
+        }
+    }
+
+    fn number(&self, value: Value) -> Result<f64, ErrorKind> {
+        match value {
+            Value::Integer(n) => Ok(n as f64),
+            Value::Float(x) => Ok(x),
+            _ => Err(ErrorKind::TypeMismatch {
+                expected: "number",
+                found: self.pool.type_name(&value),
+            }),
+        }
+    }
+
+    // Checks that `value` is a record type with `len` fields.
+    fn check_fields(&self, value: Value, len: usize) -> Result<(), ErrorKind> {
+        let shape = self
+            .pool
+            .shape(value)
+            .map_err(|_| ErrorKind::TypeMismatch {
+                expected: "record type",
+                found: self.pool.type_name(&value),
+            })?;
+        if shape.fields.len() != len {
+            return Err(ErrorKind::FieldCount {
+                record: shape.name.clone(),
+                expected: shape.fields.len(),
+                found: len,
+            });
+        }
+        Ok(())
+    }
+
+    // Finds the slot of the field called `name` in a record.
+    fn named_slot(&self, record: Value, name: &str) -> Result<usize, ErrorKind> {
+        let shape = self.pool.shape(self.pool.record(record)?.shape)?;
+        shape.slot(name).ok_or_else(|| ErrorKind::NoField {
+            record: shape.name.clone(),
+            field: name.into(),
+        })
+    }
+
+    // Stores `value` in `slot` of the record on top of the stack.
+    fn set_field(&mut self, thread: Value, slot: usize, value: Value) -> Result<(), ErrorKind> {
+        let record = self.pool.thread(thread)?.peek()?;
+        let field = self
+            .pool
+            .record_mut(record)?
+            .fields
+            .get_mut(slot)
+            .ok_or(ErrorKind::BadSlot { slot })?;
+        *field = value;
+        Ok(())
+    }
+
+    // The key and value at a position of a list or map being iterated over,
+    // where a list's keys are its indices.
+    fn entry(&self, target: Value, position: Value) -> Result<(Value, Value), ErrorKind> {
+        let i = self.pool.integer(position)?;
+        if let Ok(map) = self.pool.map(target) {
+            let i = index(i, map.len())?;
+            return Ok((map.keys[i], map.values[i]));
+        }
+        let list = self
+            .pool
+            .list(target)
+            .map_err(|_| self.not_indexable(target))?;
+        Ok((position, list[index(i, list.len())?]))
+    }
+
+    fn not_indexable(&self, value: Value) -> ErrorKind {
+        ErrorKind::TypeMismatch {
+            expected: "list or map",
+            found: self.pool.type_name(&value),
+        }
+    }
+
+    // Applies `integer` if both operands are integers. Otherwise an integer
+    // operand is promoted to the nearest float and `float` is applied.
+    fn numeric(
+        &self,
+        a: Value,
+        b: Value,
+        integer: impl FnOnce(i64, i64) -> Result<Value, ErrorKind>,
+        float: impl FnOnce(f64, f64) -> Value,
+    ) -> Result<Value, ErrorKind> {
+        match (a, b) {
+            (Value::Integer(a), Value::Integer(b)) => integer(a, b),
+            _ => Ok(float(self.number(a)?, self.number(b)?)),
+        }
+    }
+
+    // Replaces the top two values on the stack with the result of `numeric`.
+    fn arithmetic(
+        &mut self,
+        thread: Value,
+        integer: impl FnOnce(i64, i64) -> Result<Value, ErrorKind>,
+        float: impl FnOnce(f64, f64) -> Value,
+    ) -> Result<(), ErrorKind> {
+        let b = self.pool.thread_mut(thread)?.pop()?;
+        let a = self.pool.thread_mut(thread)?.pop()?;
+        let result = self.numeric(a, b, integer, float)?;
+        self.pool.thread_mut(thread)?.push(result);
+        Ok(())
+    }
+
+    fn call_builtin(
+        &mut self,
+        builtin: Builtin,
+        num_args: u32,
+        thread: Value,
+    ) -> Result<(), ErrorKind> {
+        if num_args != builtin.num_params() {
+            return Err(ErrorKind::Arity {
+                expected: builtin.num_params(),
+                found: num_args,
+            });
+        }
+        self.pool.thread_mut(thread)?.pop()?;
+        let args = self.pool.thread_mut(thread)?.pop_n(num_args as usize)?;
+        let result = builtin.call(&mut self.pool, &args)?;
+        self.pool.thread_mut(thread)?.push(result);
+        Ok(())
+    }
+
+    fn jump(&mut self, thread: Value, target: usize, code_len: usize) -> Result<(), ErrorKind> {
+        if target >= code_len {
+            return Err(ErrorKind::BadJump { target });
+        }
+        self.pool
+            .thread_mut(thread)?
+            .frames
+            .last_mut()
+            .unwrap()
+            .addr = target;
+        Ok(())
+    }
+
+    fn step(
+        &mut self,
+        expr: Expr,
+        thread: Value,
+        code_len: usize,
+        strings: &[String],
+    ) -> Result<Option<Value>, ErrorKind> {
         match expr {
+            Expr::Nil => {
+                self.pool.thread_mut(thread)?.push(Value::Nil);
+            }
+            Expr::Pop => {
+                self.pool.thread_mut(thread)?.pop()?;
+            }
+            Expr::PopBelow { count } => {
+                let thread = self.pool.thread_mut(thread)?;
+                let top = thread.pop()?;
+                thread.pop_n(count as usize)?;
+                thread.push(top);
+            }
             Expr::Load { i } => {
-                let value = self.pool.thread(thread).get(i);
-                self.pool.thread_mut(thread).push(value);
+                let value = self.pool.thread(thread)?.get(i)?;
+                self.pool.thread_mut(thread)?.push(value);
+            }
+            Expr::Store { i } => {
+                let thread = self.pool.thread_mut(thread)?;
+                let value = thread.pop()?;
+                thread.set(i, value)?;
+            }
+            Expr::NewUpvalue { i } => {
+                let value = self.pool.thread(thread)?.get(i)?;
+                let upvalue = self.pool.allocate(Object::Upvalue(value));
+                self.pool.thread_mut(thread)?.set(i, upvalue)?;
+            }
+            Expr::LoadUpvalue { i } => {
+                let upvalue = self.pool.thread(thread)?.get(i)?;
+                let value = *self.pool.upvalue(upvalue)?;
+                self.pool.thread_mut(thread)?.push(value);
+            }
+            Expr::StoreUpvalue { i } => {
+                let value = self.pool.thread_mut(thread)?.pop()?;
+                let upvalue = self.pool.thread(thread)?.get(i)?;
+                *self.pool.upvalue_mut(upvalue)? = value;
+            }
+            Expr::LoadGlobal { index, name } => {
+                let Some(value) = self.globals.get(index).copied().flatten() else {
+                    let name = strings
+                        .get(name)
+                        .ok_or(ErrorKind::BadConstant { index: name })?;
+                    return Err(ErrorKind::UndefinedGlobal { name: name.clone() });
+                };
+                self.pool.thread_mut(thread)?.push(value);
+            }
+            Expr::StoreGlobal { index } => {
+                let value = self.pool.thread_mut(thread)?.pop()?;
+                if index >= self.globals.len() {
+                    self.globals.resize(index + 1, None);
+                }
+                self.globals[index] = Some(value);
             }
             Expr::Literal { integer } => {
-                self.pool.thread_mut(thread).push(Value::Integer(integer));
+                self.pool.thread_mut(thread)?.push(Value::Integer(integer));
+            }
+            Expr::FloatLiteral { float } => {
+                self.pool.thread_mut(thread)?.push(Value::Float(float));
             }
+            Expr::BoolLiteral { boolean } => {
+                self.pool.thread_mut(thread)?.push(Value::Bool(boolean));
+            }
+            Expr::StringLiteral { index } => {
+                let string = strings.get(index).ok_or(ErrorKind::BadConstant { index })?;
+                let value = self.pool.allocate(Object::String(string.clone()));
+                self.pool.thread_mut(thread)?.push(value);
+            }
+            Expr::Builtin { builtin } => {
+                self.pool.thread_mut(thread)?.push(Value::Builtin(builtin));
+            }
+            Expr::List { len } => {
+                let items = self.pool.thread_mut(thread)?.pop_n(len as usize)?;
+                let value = self.pool.allocate(Object::List(items));
+                self.pool.thread_mut(thread)?.push(value);
+            }
+            Expr::Map { len } => {
+                let entries = self.pool.thread_mut(thread)?.pop_n(2 * len as usize)?;
+                let mut map = Map::default();
+                for entry in entries.chunks(2) {
+                    map.insert(self.pool.key(entry[0])?, entry[0], entry[1]);
+                }
+                let value = self.pool.allocate(Object::Map(map));
+                self.pool.thread_mut(thread)?.push(value);
+            }
+            Expr::Shape { name, fields, len } => {
+                let constant =
+                    |index: usize| strings.get(index).ok_or(ErrorKind::BadConstant { index });
+                let shape = Shape {
+                    name: constant(name)?.clone(),
+                    fields: (fields..fields + len as usize)
+                        .map(|index| constant(index).cloned())
+                        .collect::<Result<_, _>>()?,
+                };
+                let value = self.pool.allocate(Object::Shape(shape));
+                self.pool.thread_mut(thread)?.push(value);
+            }
+            Expr::NewRecord { len } => {
+                let shape = self.pool.thread_mut(thread)?.pop()?;
+                self.check_fields(shape, len as usize)?;
+                let record = Record {
+                    shape,
+                    fields: vec![Value::Nil; len as usize],
+                };
+                let value = self.pool.allocate(Object::Record(record));
+                self.pool.thread_mut(thread)?.push(value);
+            }
+            Expr::CopyRecord => {
+                let record = self.pool.thread_mut(thread)?.pop()?;
+                let copy = self.pool.record(record)?.clone();
+                let value = self.pool.allocate(Object::Record(copy));
+                self.pool.thread_mut(thread)?.push(value);
+            }
+            Expr::GetField { slot } => {
+                let record = self.pool.thread_mut(thread)?.pop()?;
+                let slot = slot as usize;
+                let fields = &self.pool.record(record)?.fields;
+                let value = *fields.get(slot).ok_or(ErrorKind::BadSlot { slot })?;
+                self.pool.thread_mut(thread)?.push(value);
+            }
+            Expr::GetNamedField { name } => {
+                let name = strings
+                    .get(name)
+                    .ok_or(ErrorKind::BadConstant { index: name })?;
+                let record = self.pool.thread_mut(thread)?.pop()?;
+                let slot = self.named_slot(record, name)?;
+                let value = self.pool.record(record)?.fields[slot];
+                self.pool.thread_mut(thread)?.push(value);
+            }
+            Expr::SetField { slot } => {
+                let value = self.pool.thread_mut(thread)?.pop()?;
+                self.set_field(thread, slot as usize, value)?;
+            }
+            Expr::SetNamedField { name } => {
+                let name = strings
+                    .get(name)
+                    .ok_or(ErrorKind::BadConstant { index: name })?;
+                let value = self.pool.thread_mut(thread)?.pop()?;
+                let record = self.pool.thread(thread)?.peek()?;
+                let slot = self.named_slot(record, name)?;
+                self.set_field(thread, slot, value)?;
+            }
+            Expr::TestVariant => {
+                let variant = self.pool.thread_mut(thread)?.pop()?;
+                let value = self.pool.thread_mut(thread)?.pop()?;
+                let shape = match self.pool.record(variant) {
+                    Ok(record) => record.shape,
+                    Err(_) => variant,
+                };
+                let matches = match self.pool.record(value) {
+                    Ok(record) => self.pool.equal(&record.shape, &shape),
+                    Err(_) => false,
+                };
+                self.pool.thread_mut(thread)?.push(Value::Bool(matches));
+            }
+            Expr::TestList { len, rest } => {
+                let value = self.pool.thread_mut(thread)?.pop()?;
+                let matches = match self.pool.list(value) {
+                    Ok(items) if rest => items.len() >= len as usize,
+                    Ok(items) => items.len() == len as usize,
+                    Err(_) => false,
+                };
+                self.pool.thread_mut(thread)?.push(Value::Bool(matches));
+            }
+            Expr::ListFrom { start } => {
+                let list = self.pool.thread_mut(thread)?.pop()?;
+                let items = self.pool.list(list)?;
+                let rest = items.get(start as usize..).unwrap_or_default().to_vec();
+                let value = self.pool.allocate(Object::List(rest));
+                self.pool.thread_mut(thread)?.push(value);
+            }
+            Expr::NoMatch => {
+                let value = self.pool.thread_mut(thread)?.pop()?;
+                return Err(ErrorKind::NoMatch {
+                    value: self.pool.repr(&value),
+                });
+            }
+            Expr::IterLen => {
+                let target = self.pool.thread_mut(thread)?.pop()?;
+                let len = match (self.pool.list(target), self.pool.map(target)) {
+                    (Ok(list), _) => list.len(),
+                    (_, Ok(map)) => map.len(),
+                    _ => return Err(self.not_indexable(target)),
+                };
+                self.pool
+                    .thread_mut(thread)?
+                    .push(Value::Integer(len as i64));
+            }
+            Expr::IterItem | Expr::IterKey | Expr::IterValue => {
+                let position = self.pool.thread_mut(thread)?.pop()?;
+                let target = self.pool.thread_mut(thread)?.pop()?;
+                let (key, value) = self.entry(target, position)?;
+                let is_map = self.pool.map(target).is_ok();
+                let value = match expr {
+                    Expr::IterItem if is_map => key,
+                    Expr::IterKey => key,
+                    _ => value,
+                };
+                self.pool.thread_mut(thread)?.push(value);
+            }
+            Expr::Index => {
+                let i = self.pool.thread_mut(thread)?.pop()?;
+                let target = self.pool.thread_mut(thread)?.pop()?;
+                let value = if let Ok(map) = self.pool.map(target) {
+                    let key = self.pool.key(i)?;
+                    map.get(&key).ok_or_else(|| ErrorKind::MissingKey {
+                        key: self.pool.repr(&i),
+                    })?
+                } else {
+                    let i = self.pool.integer(i)?;
+                    let list = self
+                        .pool
+                        .list(target)
+                        .map_err(|_| self.not_indexable(target))?;
+                    list[index(i, list.len())?]
+                };
+                self.pool.thread_mut(thread)?.push(value);
+            }
+            Expr::SetIndex => {
+                let value = self.pool.thread_mut(thread)?.pop()?;
+                let i = self.pool.thread_mut(thread)?.pop()?;
+                let target = self.pool.thread_mut(thread)?.pop()?;
+                if self.pool.map(target).is_ok() {
+                    let key = self.pool.key(i)?;
+                    self.pool.map_mut(target)?.insert(key, i, value);
+                } else {
+                    let i = self.pool.integer(i)?;
+                    let error = self.not_indexable(target);
+                    let list = self.pool.list_mut(target).map_err(|_| error)?;
+                    let i = index(i, list.len())?;
+                    list[i] = value;
+                }
+            }
+            Expr::Concat { count } => {
+                let values = self.pool.thread_mut(thread)?.pop_n(count as usize)?;
+                let string = values
+                    .iter()
+                    .map(|value| self.pool.to_string(value))
+                    .collect();
+                let value = self.pool.allocate(Object::String(string));
+                self.pool.thread_mut(thread)?.push(value);
+            }
+            // Float arithmetic follows IEEE 754, so dividing by zero gives an
+            // infinity or NaN rather than an error.
+            // Adding two strings concatenates them
             Expr::Add => {
-                let thread = self.pool.thread_mut(thread);
-                let b = thread.pop().integer();
-                let a = thread.pop().integer();
-                thread.push(Value::Integer(a + b));
+                let b = self.pool.thread_mut(thread)?.pop()?;
+                let a = self.pool.thread_mut(thread)?.pop()?;
+                let result = match (self.pool.string(a), self.pool.string(b)) {
+                    (Ok(a), Ok(b)) => {
+                        let string = format!("{a}{b}");
+                        self.pool.allocate(Object::String(string))
+                    }
+                    _ => self.numeric(
+                        a,
+                        b,
+                        |a, b| checked(a.checked_add(b)),
+                        |a, b| Value::Float(a + b),
+                    )?,
+                };
+                self.pool.thread_mut(thread)?.push(result);
             }
-            Expr::Sub => {
-                let thread = self.pool.thread_mut(thread);
-                let b = thread.pop().integer();
-                let a = thread.pop().integer();
-                thread.push(Value::Integer(a - b));
+            Expr::Sub => self.arithmetic(
+                thread,
+                |a, b| checked(a.checked_sub(b)),
+                |a, b| Value::Float(a - b),
+            )?,
+            Expr::Mul => self.arithmetic(
+                thread,
+                |a, b| checked(a.checked_mul(b)),
+                |a, b| Value::Float(a * b),
+            )?,
+            Expr::Div => self.arithmetic(
+                thread,
+                |a, b| {
+                    nonzero(b)?;
+                    checked(a.checked_div(b))
+                },
+                |a, b| Value::Float(a / b),
+            )?,
+            Expr::Rem => self.arithmetic(
+                thread,
+                |a, b| {
+                    nonzero(b)?;
+                    checked(a.checked_rem(b))
+                },
+                |a, b| Value::Float(a % b),
+            )?,
+            // An integer raised to a negative integer power is a float
+            Expr::Pow => self.arithmetic(
+                thread,
+                |a, b| match u32::try_from(b) {
+                    Ok(b) => checked(a.checked_pow(b)),
+                    Err(_) if b < 0 => Ok(Value::Float((a as f64).powf(b as f64))),
+                    Err(_) => Err(ErrorKind::Overflow),
+                },
+                |a, b| Value::Float(a.powf(b)),
+            )?,
+            Expr::Eq | Expr::Ne => {
+                let b = self.pool.thread_mut(thread)?.pop()?;
+                let a = self.pool.thread_mut(thread)?.pop()?;
+                let equal = self.pool.equal(&a, &b);
+                let result = Value::Bool(equal == matches!(expr, Expr::Eq));
+                self.pool.thread_mut(thread)?.push(result);
+            }
+            Expr::Lt => self.arithmetic(
+                thread,
+                |a, b| Ok(Value::Bool(a < b)),
+                |a, b| Value::Bool(a < b),
+            )?,
+            Expr::Le => self.arithmetic(
+                thread,
+                |a, b| Ok(Value::Bool(a <= b)),
+                |a, b| Value::Bool(a <= b),
+            )?,
+            Expr::Gt => self.arithmetic(
+                thread,
+                |a, b| Ok(Value::Bool(a > b)),
+                |a, b| Value::Bool(a > b),
+            )?,
+            Expr::Ge => self.arithmetic(
+                thread,
+                |a, b| Ok(Value::Bool(a >= b)),
+                |a, b| Value::Bool(a >= b),
+            )?,
+            Expr::Neg => {
+                let value = self.pool.thread_mut(thread)?.pop()?;
+                let result = match value {
+                    Value::Integer(n) => checked(n.checked_neg())?,
+                    _ => Value::Float(-self.number(value)?),
+                };
+                self.pool.thread_mut(thread)?.push(result);
+            }
+            Expr::Not => {
+                let value = self.pool.thread_mut(thread)?.pop()?;
+                let result = Value::Bool(!self.bool(value)?);
+                self.pool.thread_mut(thread)?.push(result);
             }
             Expr::Function {
                 entry: first_expr,
@@ -69,46 +774,71 @@ impl VM {
                 let closure = Object::Function(Function {
                     entry: first_expr,
                     num_params,
-                    closure: self.pool.thread_mut(thread).pop_n(closure_len as usize),
+                    closure: self.pool.thread_mut(thread)?.pop_n(closure_len as usize)?,
                 });
                 let value = self.pool.allocate(closure);
-                self.pool.thread_mut(thread).push(value);
+                self.pool.thread_mut(thread)?.push(value);
             }
-            Expr::BranchIfNotZero { target } => {
-                if self.pool.thread_mut(thread).pop().integer() != 0 {
-                    self.pool.thread_mut(thread).frames.last_mut().unwrap().addr = target;
+            Expr::BranchIfFalse { target } => {
+                let value = self.pool.thread_mut(thread)?.pop()?;
+                if !self.bool(value)? {
+                    self.jump(thread, target, code_len)?;
                 }
             }
-            Expr::Branch { target } => {
-                self.pool.thread_mut(thread).frames.last_mut().unwrap().addr = target;
-            }
-            Expr::Call { num_args } => {
-                let value = self.pool.thread(thread).peek();
-                let function = self.pool.function(value).clone();
+            Expr::Branch { target } => self.jump(thread, target, code_len)?,
+            Expr::Call { num_args } | Expr::TailCall { num_args } => {
+                let value = self.pool.thread(thread)?.peek()?;
+                if let Value::Builtin(builtin) = value {
+                    return self.call_builtin(builtin, num_args, thread).map(|_| None);
+                }
+                // Calling a record type with positional arguments constructs
+                // a record from them in field order
+                if self.pool.shape(value).is_ok() {
+                    self.check_fields(value, num_args as usize)?;
+                    self.pool.thread_mut(thread)?.pop()?;
+                    let fields = self.pool.thread_mut(thread)?.pop_n(num_args as usize)?;
+                    let record = Record {
+                        shape: value,
+                        fields,
+                    };
+                    let value = self.pool.allocate(Object::Record(record));
+                    self.pool.thread_mut(thread)?.push(value);
+                    return Ok(None);
+                }
+                let function = self.pool.function(value)?.clone();
                 if num_args != function.num_params {
-                    panic!(
-                        "function called with {} args but expected {}",
-                        num_args, function.num_params
-                    );
+                    return Err(ErrorKind::Arity {
+                        expected: function.num_params,
+                        found: num_args,
+                    });
+                }
+                if function.entry >= code_len {
+                    return Err(ErrorKind::BadJump {
+                        target: function.entry,
+                    });
+                }
+                let thread = self.pool.thread_mut(thread)?;
+                match expr {
+                    Expr::TailCall { .. } => thread.tail_call(function)?,
+                    _ => thread.call(function)?,
                 }
-                self.pool.thread_mut(thread).call(function);
             }
             Expr::Return => {
-                let thread = self.pool.thread_mut(thread);
-                thread.ret();
+                let thread = self.pool.thread_mut(thread)?;
+                thread.ret()?;
                 if thread.done() {
-                    return Some(thread.pop());
+                    return Ok(Some(thread.pop()?));
                 }
             }
         }
-        None
+        Ok(None)
     }
 
-    fn debug_step(&self, exprs: &[Expr], addr: usize, thread: Value) {
-        let frame = self.pool.thread(thread).frames.last().unwrap();
+    fn debug_step(&self, exprs: &[Expr], addr: usize, thread: &Thread) {
+        let frame = thread.frames.last().unwrap();
         println!("stack (+{}):", frame.stack_offset);
-        for i in (frame.stack_offset..self.pool.thread(thread).stack.len()).rev() {
-            let value = &self.pool.thread(thread).stack[i];
+        for i in (frame.stack_offset..thread.stack.len()).rev() {
+            let value = &thread.stack[i];
             println!(
                 "{:>4}| {}",
                 i - frame.stack_offset,
@@ -117,8 +847,43 @@ impl VM {
         }
         print!("{addr:04} -> ");
         match exprs[addr] {
+            Expr::Nil => println!("nil"),
+            Expr::Pop => println!("pop"),
+            Expr::PopBelow { count } => println!("pop below {count}"),
             Expr::Load { i } => println!("load {i}"),
+            Expr::Store { i } => println!("store {i}"),
+            Expr::NewUpvalue { i } => println!("new upvalue {i}"),
+            Expr::LoadUpvalue { i } => println!("load upvalue {i}"),
+            Expr::StoreUpvalue { i } => println!("store upvalue {i}"),
+            Expr::LoadGlobal { index, .. } => println!("load global {index}"),
+            Expr::StoreGlobal { index } => println!("store global {index}"),
             Expr::Literal { integer } => println!("literal {integer}"),
+            Expr::FloatLiteral { float } => println!("literal {float:?}"),
+            Expr::BoolLiteral { boolean } => println!("literal {boolean}"),
+            Expr::StringLiteral { index } => println!("string {index}"),
+            Expr::Builtin { builtin } => println!("builtin {}", builtin.name()),
+            Expr::Concat { count } => println!("concat {count}"),
+            Expr::List { len } => println!("list len:{len}"),
+            Expr::Map { len } => println!("map len:{len}"),
+            Expr::Shape { name, fields, len } => {
+                println!("shape name:{name} fields:{fields} len:{len}")
+            }
+            Expr::NewRecord { len } => println!("new record len:{len}"),
+            Expr::CopyRecord => println!("copy record"),
+            Expr::GetField { slot } => println!("get field {slot}"),
+            Expr::GetNamedField { name } => println!("get field named {name}"),
+            Expr::SetField { slot } => println!("set field {slot}"),
+            Expr::SetNamedField { name } => println!("set field named {name}"),
+            Expr::TestVariant => println!("test variant"),
+            Expr::TestList { len, rest } => println!("test list len:{len} rest:{rest}"),
+            Expr::ListFrom { start } => println!("list from {start}"),
+            Expr::NoMatch => println!("no match"),
+            Expr::IterLen => println!("iter len"),
+            Expr::IterItem => println!("iter item"),
+            Expr::IterKey => println!("iter key"),
+            Expr::IterValue => println!("iter value"),
+            Expr::Index => println!("index"),
+            Expr::SetIndex => println!("set index"),
             Expr::Function {
                 entry,
                 closure_len,
@@ -128,31 +893,99 @@ impl VM {
             }
             Expr::Return => println!("return"),
             Expr::Call { num_args } => println!("call args:{num_args}"),
+            Expr::TailCall { num_args } => println!("tail call args:{num_args}"),
             Expr::Add => println!("add"),
             Expr::Sub => println!("sub"),
-            Expr::BranchIfNotZero { target } => {
-                println!("branch if not zero target{target}")
-            }
+            Expr::Mul => println!("mul"),
+            Expr::Div => println!("div"),
+            Expr::Rem => println!("rem"),
+            Expr::Pow => println!("pow"),
+            Expr::Eq => println!("eq"),
+            Expr::Ne => println!("ne"),
+            Expr::Lt => println!("lt"),
+            Expr::Le => println!("le"),
+            Expr::Gt => println!("gt"),
+            Expr::Ge => println!("ge"),
+            Expr::Neg => println!("neg"),
+            Expr::Not => println!("not"),
+            Expr::BranchIfFalse { target } => println!("branch if false target{target}"),
             Expr::Branch { target } => println!("branch target{target}"),
         }
     }
 
-    pub fn exec(&mut self, exprs: &[Expr], entry: usize) -> Value {
-        let thread = self.pool.allocate(Object::Thread(Thread::new(entry)));
+    fn run(
+        &mut self,
+        exprs: &[Expr],
+        strings: &[String],
+        thread: Value,
+        addr: &mut usize,
+    ) -> Result<Value, ErrorKind> {
         loop {
-            let addr = self.pool.thread_mut(thread).advance().unwrap();
+            *addr = self.pool.thread_mut(thread)?.advance().unwrap();
+            let Some(&expr) = exprs.get(*addr) else {
+                return Err(ErrorKind::BadJump { target: *addr });
+            };
             if self.debug {
-                self.debug_step(exprs, addr, thread);
+                self.debug_step(exprs, *addr, self.pool.thread(thread)?);
             }
-            if let Some(result) = self.step(exprs[addr], thread) {
-                let num_objects = self.pool.len();
-                let result = self.pool.compact(result);
-                if self.debug {
-                    println!("reclaimed {} objects", num_objects - self.pool.len());
-                }
-                return result;
-            };
+            if let Some(result) = self.step(expr, thread, exprs.len(), strings)? {
+                return Ok(result);
+            }
+        }
+    }
+
+    fn backtrace(&self, thread: Value, addr: usize) -> Vec<TraceFrame> {
+        let Ok(thread) = self.pool.thread(thread) else {
+            return Vec::new();
+        };
+        // Every frame but the innermost is part way through a call, and has
+        // already advanced past it.
+        thread
+            .frames
+            .iter()
+            .rev()
+            .enumerate()
+            .map(|(i, frame)| TraceFrame {
+                entry: frame.entry,
+                addr: if i == 0 { addr } else { frame.addr - 1 },
+            })
+            .collect()
+    }
+
+    /// Runs the program `exprs` from `entry`, where `Expr::StringLiteral`s
+    /// refer to `strings`.
+    pub fn exec(
+        &mut self,
+        exprs: &[Expr],
+        strings: &[String],
+        entry: usize,
+    ) -> Result<Value, RuntimeError> {
+        let thread = self.pool.allocate(Object::Thread(Thread::new(entry)));
+        let mut addr = entry;
+        let result = self.run(exprs, strings, thread, &mut addr);
+        let result = result.map_err(|kind| RuntimeError {
+            addr,
+            kind,
+            backtrace: self.backtrace(thread, addr),
+        });
+        let num_objects = self.pool.len();
+        // Globals outlive the program, but a failed program's result is
+        // nothing worth keeping
+        let globals = self.globals.iter_mut().flatten();
+        let result = match result {
+            Ok(mut result) => {
+                self.pool.compact(globals.chain([&mut result]));
+                Ok(result)
+            }
+            Err(err) => {
+                self.pool.compact(globals);
+                Err(err)
+            }
+        };
+        if self.debug {
+            println!("reclaimed {} objects", num_objects - self.pool.len());
         }
+        result
     }
 }
 
@@ -174,14 +1007,16 @@ mod test {
             // stack is: 0:n, 1:func
             // fib := (n) => { if i == 0 { return 0; }
             Expr::Load { i: 0 },
-            Expr::BranchIfNotZero { target: 4 },
+            Expr::Literal { integer: 0 },
+            Expr::Eq,
+            Expr::BranchIfFalse { target: 6 },
             Expr::Literal { integer: 0 },
             Expr::Return,
             // else if i == 1 { return 1; }
             Expr::Load { i: 0 },
             Expr::Literal { integer: 1 },
-            Expr::Sub,
-            Expr::BranchIfNotZero { target: 10 },
+            Expr::Eq,
+            Expr::BranchIfFalse { target: 12 },
             Expr::Literal { integer: 1 },
             Expr::Return,
             // else { return fib(n - 2) + fib(n - 1) } }
@@ -209,7 +1044,7 @@ mod test {
         ];
         let mut vm = VM::new();
         vm.debug = true;
-        assert_eq!(vm.exec(&exprs, 22).integer(), 21);
+        assert_eq!(vm.exec(&exprs, &[], 24).unwrap().integer(), Some(21));
     }
 
     #[test]
@@ -249,6 +1084,132 @@ mod test {
         ];
         let mut vm = VM::new();
         vm.debug = true;
-        assert_eq!(vm.exec(&exprs, 7).integer(), 3);
+        assert_eq!(vm.exec(&exprs, &[], 7).unwrap().integer(), Some(3));
+    }
+
+    #[test]
+    fn test_globals() {
+        let strings = ["discarded".to_string(), "kept".to_string()];
+        let exprs = [
+            Expr::StringLiteral { index: 0 },
+            Expr::Pop,
+            Expr::StringLiteral { index: 1 },
+            Expr::StoreGlobal { index: 0 },
+            Expr::Nil,
+            Expr::Return,
+        ];
+        let mut vm = VM::new();
+        vm.debug = true;
+        assert!(matches!(vm.exec(&exprs, &strings, 0), Ok(Value::Nil)));
+        // Only the global's string survives compaction
+        assert_eq!(vm.pool.len(), 1);
+        let exprs = [Expr::LoadGlobal { index: 0, name: 1 }, Expr::Return];
+        let value = vm.exec(&exprs, &strings, 0).unwrap();
+        assert_eq!(vm.pool.string(value).map(String::as_str), Ok("kept"));
+        let exprs = [Expr::LoadGlobal { index: 1, name: 0 }, Expr::Return];
+        assert_eq!(
+            vm.exec(&exprs, &strings, 0).err().map(|err| err.kind),
+            Some(ErrorKind::UndefinedGlobal {
+                name: "discarded".into()
+            })
+        );
+    }
+
+    #[test]
+    fn test_errors() {
+        let mut vm = VM::new();
+        let error = |addr, kind| Some((addr, kind));
+        let exprs = [Expr::Literal { integer: 1 }, Expr::Add, Expr::Return];
+        assert_eq!(
+            vm.exec(&exprs, &[], 0)
+                .err()
+                .map(|err| (err.addr, err.kind)),
+            error(1, ErrorKind::StackUnderflow)
+        );
+        let exprs = [Expr::Branch { target: 3 }];
+        assert_eq!(
+            vm.exec(&exprs, &[], 0)
+                .err()
+                .map(|err| (err.addr, err.kind)),
+            error(0, ErrorKind::BadJump { target: 3 })
+        );
+        let exprs = [Expr::Literal { integer: 1 }, Expr::Call { num_args: 0 }];
+        assert_eq!(
+            vm.exec(&exprs, &[], 0)
+                .err()
+                .map(|err| (err.addr, err.kind)),
+            error(
+                1,
+                ErrorKind::TypeMismatch {
+                    expected: "function",
+                    found: "integer"
+                }
+            )
+        );
+        let exprs = [
+            Expr::Return,
+            Expr::Function {
+                entry: 0,
+                closure_len: 0,
+                num_params: 1,
+            },
+            Expr::Nil,
+            Expr::Add,
+        ];
+        assert_eq!(
+            vm.exec(&exprs, &[], 1)
+                .err()
+                .map(|err| (err.addr, err.kind)),
+            error(
+                3,
+                ErrorKind::TypeMismatch {
+                    expected: "number",
+                    found: "function"
+                }
+            )
+        );
+        let exprs = [
+            Expr::Return,
+            Expr::Function {
+                entry: 0,
+                closure_len: 0,
+                num_params: 1,
+            },
+            Expr::Call { num_args: 0 },
+        ];
+        assert_eq!(
+            vm.exec(&exprs, &[], 1)
+                .err()
+                .map(|err| (err.addr, err.kind)),
+            error(
+                2,
+                ErrorKind::Arity {
+                    expected: 1,
+                    found: 0
+                }
+            )
+        );
+        let exprs = [
+            Expr::Literal { integer: i64::MAX },
+            Expr::Literal { integer: 1 },
+            Expr::Add,
+        ];
+        assert_eq!(
+            vm.exec(&exprs, &[], 0)
+                .err()
+                .map(|err| (err.addr, err.kind)),
+            error(2, ErrorKind::Overflow)
+        );
+        let exprs = [
+            Expr::Literal { integer: 1 },
+            Expr::Literal { integer: 0 },
+            Expr::Rem,
+        ];
+        assert_eq!(
+            vm.exec(&exprs, &[], 0)
+                .err()
+                .map(|err| (err.addr, err.kind)),
+            error(2, ErrorKind::DivisionByZero)
+        );
     }
 }